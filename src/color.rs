@@ -0,0 +1,141 @@
+use crate::config::ColorChoice;
+use std::env;
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Used when `LS_COLORS` isn't set, matching dircolors(1).
+const DEFAULT_LS_COLORS: &str = "di=01;34:ln=01;36:pi=40;33:so=01;35:bd=40;33;01:cd=40;33;01:or=40;31;01:ex=01;32";
+
+const DIM: &str = "2";
+const RED: &str = "31";
+
+struct Colors {
+  stdout: bool,
+  stderr: bool,
+  /// `LS_COLORS` entries as (key, SGR parameters)
+  ls_colors: Vec<(String, String)>,
+}
+
+static COLORS: OnceLock<Colors> = OnceLock::new();
+
+/// Decide once whether stdout and stderr get colored. Until this is
+/// called, nothing is.
+pub fn init(choice: ColorChoice) {
+  let enabled = |fd| match choice {
+    ColorChoice::Always => true,
+    ColorChoice::Never => false,
+    ColorChoice::Auto => {
+      env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+        && unsafe { libc::isatty(fd) } == 1
+    }
+  };
+  let ls_colors = env::var("LS_COLORS")
+    .ok()
+    .filter(|v| !v.is_empty())
+    .unwrap_or_else(|| DEFAULT_LS_COLORS.to_string())
+    .split(':')
+    .filter_map(|item| {
+      let (key, value) = item.split_once('=')?;
+      Some((key.to_string(), value.to_string()))
+    })
+    .collect();
+  let _ = COLORS.set(Colors {
+    stdout: enabled(libc::STDOUT_FILENO),
+    stderr: enabled(libc::STDERR_FILENO),
+    ls_colors,
+  });
+}
+
+fn paint(enabled: bool, sgr: &str, text: impl Display) -> String {
+  if enabled && !sgr.is_empty() {
+    format!("\x1b[{}m{}\x1b[0m", sgr, text)
+  } else {
+    text.to_string()
+  }
+}
+
+fn stdout() -> bool {
+  COLORS.get().is_some_and(|c| c.stdout)
+}
+
+/// `name` colored for stdout the way ls(1) would color the file at
+/// `path`.
+pub fn name(path: &Path, name: &OsStr) -> String {
+  let text = Path::new(name).display();
+  match COLORS.get() {
+    Some(colors) if colors.stdout => {
+      paint(true, colors.ls_color(path, name), text)
+    }
+    _ => text.to_string(),
+  }
+}
+
+/// Secondary text on stdout, such as original paths.
+pub fn dim(text: impl Display) -> String {
+  paint(stdout(), DIM, text)
+}
+
+/// A warning or error for stderr.
+pub fn warning(text: impl Display) -> String {
+  paint(COLORS.get().is_some_and(|c| c.stderr), RED, text)
+}
+
+impl Colors {
+  fn get(&self, key: &str) -> Option<&str> {
+    self
+      .ls_colors
+      .iter()
+      .find(|(k, _)| k == key)
+      .map(|(_, v)| v.as_str())
+  }
+
+  fn ls_color(&self, path: &Path, name: &OsStr) -> &str {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+      return "";
+    };
+    if !meta.file_type().is_symlink() {
+      return self.ls_color_of(&meta, name);
+    }
+    match fs::metadata(path) {
+      Err(_) => self.get("or").or(self.get("ln")).unwrap_or(""),
+      Ok(target) if self.get("ln") == Some("target") => {
+        self.ls_color_of(&target, name)
+      }
+      Ok(_) => self.get("ln").unwrap_or(""),
+    }
+  }
+
+  fn ls_color_of(&self, meta: &fs::Metadata, name: &OsStr) -> &str {
+    let file_type = meta.file_type();
+    let key = if file_type.is_dir() {
+      "di"
+    } else if file_type.is_fifo() {
+      "pi"
+    } else if file_type.is_socket() {
+      "so"
+    } else if file_type.is_block_device() {
+      "bd"
+    } else if file_type.is_char_device() {
+      "cd"
+    } else if meta.permissions().mode() & 0o111 != 0 {
+      "ex"
+    } else {
+      // Suffix patterns like `*.tar` only apply to regular files
+      let name = name.as_bytes();
+      let suffix = self.ls_colors.iter().find(|(k, _)| {
+        k.strip_prefix('*')
+          .is_some_and(|suffix| name.ends_with(suffix.as_bytes()))
+      });
+      return match suffix {
+        Some((_, sgr)) => sgr,
+        None => self.get("fi").unwrap_or(""),
+      };
+    };
+    self.get(key).unwrap_or("")
+  }
+}
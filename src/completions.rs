@@ -0,0 +1,368 @@
+use crate::{
+  value_options, Command, Flag, COMMANDS, GLOBAL_OPTIONS, HELP, MAN,
+};
+use std::str::FromStr;
+
+const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+#[derive(Debug, Clone, Copy)]
+pub enum Shell {
+  Bash,
+  Zsh,
+  Fish,
+}
+
+impl FromStr for Shell {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "bash" => Ok(Self::Bash),
+      "zsh" => Ok(Self::Zsh),
+      "fish" => Ok(Self::Fish),
+      _ => Err(format!(
+        "Unknown shell `{}`, expected bash, zsh or fish",
+        s
+      )),
+    }
+  }
+}
+
+/// The completion script for `shell`, generated from the same
+/// command and option tables the parser uses.
+pub fn script(shell: Shell) -> String {
+  match shell {
+    Shell::Bash => bash(),
+    Shell::Zsh => zsh(),
+    Shell::Fish => fish(),
+  }
+}
+
+fn commands() -> impl Iterator<Item = &'static Command> {
+  COMMANDS.iter().filter(|c| !c.hidden)
+}
+
+/// Options accepted once `command` has been selected.
+fn options(command: &'static Command) -> Vec<&'static Flag> {
+  GLOBAL_OPTIONS
+    .iter()
+    .chain(command.options)
+    .chain([&HELP])
+    .collect()
+}
+
+/// Commands whose operands are names of trashed items.
+fn entry_commands() -> Vec<&'static str> {
+  commands()
+    .filter(|c| c.operands.starts_with("name"))
+    .map(|c| c.name)
+    .collect()
+}
+
+/// Options that take a value, each listed once.
+fn value_flags() -> Vec<&'static Flag> {
+  let mut flags: Vec<&Flag> = Vec::new();
+  for flag in value_options() {
+    if !flags.iter().any(|f| f.name == flag.name) {
+      flags.push(flag);
+    }
+  }
+  flags
+}
+
+fn spellings(flag: &Flag) -> Vec<String> {
+  let mut spellings: Vec<String> =
+    flag.short.iter().map(|s| s.to_string()).collect();
+  spellings.push(format!("--{}", flag.name));
+  spellings
+}
+
+/// Options accepted before a command is named.
+fn leading_flags() -> Vec<&'static Flag> {
+  let mut flags = options(&COMMANDS[0]);
+  flags.push(&MAN);
+  flags
+}
+
+/// Every option accepted before a command is named: those of the
+/// default command plus the option spellings of other commands.
+fn leading_options() -> Vec<String> {
+  let mut words: Vec<String> =
+    leading_flags().into_iter().flat_map(spellings).collect();
+  for command in commands() {
+    words.extend(command.aliases.iter().map(|a| a.to_string()));
+  }
+  words
+}
+
+/// Quote `s` for bash and zsh.
+fn quote(s: &str) -> String {
+  format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Quote `s` for fish, where only `\` and `'` are special inside
+/// single quotes.
+fn fish_quote(s: &str) -> String {
+  format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// `case` patterns matching each command by name or alias.
+fn command_patterns() -> Vec<(String, &'static str)> {
+  commands()
+    .map(|command| {
+      let mut patterns = vec![command.name.to_string()];
+      patterns.extend(command.aliases.iter().map(|a| a.to_string()));
+      (patterns.join("|"), command.name)
+    })
+    .collect()
+}
+
+fn bash() -> String {
+  let mut out = String::from("# bash completion for can\n_can() {\n");
+  out.push_str("  local cur=${COMP_WORDS[COMP_CWORD]}\n");
+  out.push_str("  local prev=${COMP_WORDS[COMP_CWORD-1]}\n");
+  out.push_str("  local cmd= opts i\n\n");
+  out.push_str("  for ((i = 1; i < COMP_CWORD; i++)); do\n");
+  out.push_str("    case ${COMP_WORDS[i]} in\n");
+  out.push_str("      --) break ;;\n");
+  for (patterns, name) in command_patterns() {
+    out.push_str(&format!(
+      "      {}) cmd={}; break ;;\n",
+      patterns, name
+    ));
+  }
+  out.push_str("    esac\n  done\n\n");
+
+  out.push_str("  case $prev in\n");
+  for flag in value_flags() {
+    if flag.choices.is_empty() {
+      out.push_str(&format!("    --{}) return ;;\n", flag.name));
+    } else {
+      out.push_str(&format!(
+        "    --{})\n      COMPREPLY=($(compgen -W {} -- \"$cur\"))\n      return ;;\n",
+        flag.name,
+        quote(&flag.choices.join(" "))
+      ));
+    }
+  }
+  out.push_str("  esac\n\n");
+
+  out.push_str("  case $cmd in\n");
+  out.push_str(&format!(
+    "    '') opts={} ;;\n",
+    quote(&leading_options().join(" "))
+  ));
+  for command in commands() {
+    let words: Vec<String> =
+      options(command).into_iter().flat_map(spellings).collect();
+    out.push_str(&format!(
+      "    {}) opts={} ;;\n",
+      command.name,
+      quote(&words.join(" "))
+    ));
+  }
+  out.push_str("  esac\n");
+  out.push_str("  if [[ $cur == -* ]]; then\n");
+  out.push_str(
+    "    COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))\n",
+  );
+  out.push_str("    return\n  fi\n\n");
+
+  let names: Vec<&str> = commands().map(|c| c.name).collect();
+  out.push_str("  case $cmd in\n");
+  out.push_str(&format!(
+    "    {})\n      compopt -o filenames\n",
+    entry_commands().join("|")
+  ));
+  out.push_str("      mapfile -t COMPREPLY < <(can __complete-entries \"$cur\" 2>/dev/null) ;;\n");
+  out.push_str(&format!(
+    "    completions)\n      COMPREPLY=($(compgen -W {} -- \"$cur\")) ;;\n",
+    quote(&SHELLS.join(" "))
+  ));
+  out.push_str("    put)\n      compopt -o filenames\n");
+  out.push_str(
+    "      mapfile -t COMPREPLY < <(compgen -f -- \"$cur\") ;;\n",
+  );
+  out.push_str("    '')\n      compopt -o filenames\n");
+  out.push_str(&format!(
+    "      mapfile -t COMPREPLY < <(compgen -W {} -- \"$cur\"; compgen -f -- \"$cur\") ;;\n",
+    quote(&names.join(" "))
+  ));
+  out.push_str("  esac\n}\n\ncomplete -F _can can\n");
+  out
+}
+
+/// `_describe` entries for the options of `flags`.
+fn zsh_describe(flags: &[&Flag]) -> Vec<String> {
+  flags
+    .iter()
+    .flat_map(|flag| {
+      spellings(flag).into_iter().map(|spelling| {
+        quote(&format!("{}:{}", spelling, flag.description))
+      })
+    })
+    .collect()
+}
+
+fn zsh() -> String {
+  let mut out = String::from("#compdef can\n\n_can() {\n");
+  out.push_str("  local cmd i\n");
+  out.push_str("  local -a opts entries\n\n");
+  out.push_str("  for ((i = 2; i < CURRENT; i++)); do\n");
+  out.push_str("    case $words[i] in\n");
+  out.push_str("      (--) break ;;\n");
+  for (patterns, name) in command_patterns() {
+    out.push_str(&format!(
+      "      ({}) cmd={}; break ;;\n",
+      patterns, name
+    ));
+  }
+  out.push_str("    esac\n  done\n\n");
+
+  out.push_str("  case $words[CURRENT-1] in\n");
+  for flag in value_flags() {
+    if flag.choices.is_empty() {
+      out.push_str(&format!("    (--{}) return ;;\n", flag.name));
+    } else {
+      out.push_str(&format!(
+        "    (--{}) compadd -- {}; return ;;\n",
+        flag.name,
+        flag.choices.join(" ")
+      ));
+    }
+  }
+  out.push_str("  esac\n\n");
+
+  out.push_str("  case $cmd in\n");
+  let mut leading = zsh_describe(&leading_flags());
+  for command in commands() {
+    leading.extend(command.aliases.iter().map(|alias| {
+      quote(&format!("{}:{}", alias, command.description))
+    }));
+  }
+  out.push_str(&format!(
+    "    ('') opts=(\n      {}\n    ) ;;\n",
+    leading.join("\n      ")
+  ));
+  for command in commands() {
+    out.push_str(&format!(
+      "    ({}) opts=(\n      {}\n    ) ;;\n",
+      command.name,
+      zsh_describe(&options(command)).join("\n      ")
+    ));
+  }
+  out.push_str("  esac\n");
+  out.push_str("  if [[ $PREFIX == -* ]]; then\n");
+  out.push_str("    _describe -t options option opts\n");
+  out.push_str("    return\n  fi\n\n");
+
+  let described: Vec<String> = commands()
+    .map(|c| quote(&format!("{}:{}", c.name, c.description)))
+    .collect();
+  out.push_str("  case $cmd in\n");
+  out.push_str(&format!("    ({})\n", entry_commands().join("|")));
+  out.push_str("      entries=(${(f)\"$(_call_program entries can __complete-entries 2>/dev/null)\"})\n");
+  out.push_str("      compadd -a entries ;;\n");
+  out.push_str(&format!(
+    "    (completions) compadd -- {} ;;\n",
+    SHELLS.join(" ")
+  ));
+  out.push_str("    (put) _files ;;\n");
+  out.push_str("    ('')\n      local -a commands\n");
+  out.push_str(&format!(
+    "      commands=(\n        {}\n      )\n",
+    described.join("\n        ")
+  ));
+  out.push_str("      _describe -t commands command commands\n");
+  out.push_str("      _files ;;\n");
+  out.push_str("  esac\n}\n\n");
+  out.push_str(
+    "if [[ $zsh_eval_context[-1] == loadautofunc ]]; then\n",
+  );
+  out.push_str("  _can \"$@\"\nelse\n  compdef _can can\nfi\n");
+  out
+}
+
+/// A fish `complete` line for `flag` under `condition`.
+fn fish_option(condition: &str, flag: &Flag) -> String {
+  let mut line = format!(
+    "complete -c can -n {} -l {}",
+    fish_quote(condition),
+    flag.name
+  );
+  if let Some(short) = flag.short {
+    line.push_str(&format!(" -s {}", short.trim_start_matches('-')));
+  }
+  if flag.value.is_some() {
+    line.push_str(" -x");
+  }
+  if !flag.choices.is_empty() {
+    line.push_str(&format!(
+      " -a {}",
+      fish_quote(&flag.choices.join(" "))
+    ));
+  }
+  line.push_str(&format!(" -d {}\n", fish_quote(flag.description)));
+  line
+}
+
+fn fish() -> String {
+  let mut out = String::from("# fish completion for can\n");
+  out.push_str("function __can_command\n");
+  out.push_str("  for token in (commandline -opc)[2..-1]\n");
+  out.push_str("    switch $token\n");
+  out.push_str("      case --\n        break\n");
+  for (patterns, name) in command_patterns() {
+    out.push_str(&format!(
+      "      case {}\n        echo {}\n        return\n",
+      patterns.replace('|', " "),
+      name
+    ));
+  }
+  out.push_str("    end\n  end\n  return 1\nend\n\n");
+  out.push_str("function __can_using\n");
+  out.push_str("  set -l cmd (__can_command)\n");
+  out.push_str(&format!(
+    "  test -n \"$cmd\"; or set cmd {}\n",
+    COMMANDS[0].name
+  ));
+  out.push_str("  contains -- $cmd $argv\nend\n\n");
+
+  // Files only make sense for the default command
+  out.push_str(&format!(
+    "complete -c can -n 'not __can_using {}' -f\n",
+    COMMANDS[0].name
+  ));
+  for command in commands() {
+    out.push_str(&format!(
+      "complete -c can -n 'not __can_command' -a {} -d {}\n",
+      command.name,
+      fish_quote(command.description)
+    ));
+    for alias in command.aliases {
+      let spelling = match alias.strip_prefix("--") {
+        Some(long) => format!("-l {}", long),
+        None => format!("-s {}", alias.trim_start_matches('-')),
+      };
+      out.push_str(&format!(
+        "complete -c can -n 'not __can_command' {} -d {}\n",
+        spelling,
+        fish_quote(command.description)
+      ));
+    }
+    let condition = format!("__can_using {}", command.name);
+    for flag in options(command) {
+      out.push_str(&fish_option(&condition, flag));
+    }
+  }
+  out.push_str(&fish_option("not __can_command", &MAN));
+  out.push_str(&format!(
+    "complete -c can -n '__can_using {}' -a '(can __complete-entries 2>/dev/null)'\n",
+    entry_commands().join(" ")
+  ));
+  out.push_str(&format!(
+    "complete -c can -n '__can_using completions' -a {}\n",
+    fish_quote(&SHELLS.join(" "))
+  ));
+  out
+}
@@ -0,0 +1,605 @@
+use crate::fail;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use toml::{Table, Value};
+use xdg::BaseDirectories;
+
+/// Filesystems that never hold user data worth scanning for a
+/// topdir trash. A trailing `*` matches any suffix.
+const PSEUDO_FILESYSTEMS: &[&str] = &[
+  "autofs",
+  "binfmt_misc",
+  "bpf",
+  "cgroup",
+  "cgroup2",
+  "configfs",
+  "debugfs",
+  "devpts",
+  "devtmpfs",
+  "efivarfs",
+  "fuse.*",
+  "fusectl",
+  "hugetlbfs",
+  "mqueue",
+  "nsfs",
+  "overlay",
+  "proc",
+  "pstore",
+  "rpc_pipefs",
+  "securityfs",
+  "selinuxfs",
+  "squashfs",
+  "sysfs",
+  "tracefs",
+];
+
+/// Filesystems that may block for a long time when the server is
+/// unreachable.
+const NETWORK_FILESYSTEMS: &[&str] = &[
+  "9p",
+  "afs",
+  "ceph",
+  "cifs",
+  "davfs",
+  "fuse.glusterfs",
+  "fuse.rclone",
+  "fuse.sshfs",
+  "glusterfs",
+  "lustre",
+  "ncpfs",
+  "nfs",
+  "nfs4",
+  "smb3",
+  "smbfs",
+];
+
+#[derive(Debug, Default)]
+pub struct Config {
+  pub verbose: bool,
+  pub color: ColorChoice,
+  pub list: ListConfig,
+  pub put: PutConfig,
+  pub empty: EmptyConfig,
+  pub filesystems: FilesystemPolicy,
+  pub protect: ProtectPolicy,
+}
+
+/// When to color output.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+  /// Only on a terminal, and not when `NO_COLOR` is set
+  #[default]
+  Auto,
+  Always,
+  Never,
+}
+
+impl FromStr for ColorChoice {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "auto" => Ok(Self::Auto),
+      "always" => Ok(Self::Always),
+      "never" => Ok(Self::Never),
+      _ => {
+        Err("expected \"auto\", \"always\" or \"never\"".to_string())
+      }
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct ListConfig {
+  pub format: ListFormat,
+  pub sort: SortOrder,
+  pub date: DateFormat,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ListFormat {
+  #[default]
+  Short,
+  Long,
+}
+
+impl FromStr for ListFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "short" => Ok(Self::Short),
+      "long" => Ok(Self::Long),
+      _ => Err("expected \"short\" or \"long\"".to_string()),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+  #[default]
+  Name,
+  Date,
+  Original,
+}
+
+impl FromStr for SortOrder {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "name" => Ok(Self::Name),
+      "date" => Ok(Self::Date),
+      "original" => Ok(Self::Original),
+      _ => {
+        Err("expected \"name\", \"date\" or \"original\"".to_string())
+      }
+    }
+  }
+}
+
+/// How deletion dates are shown.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum DateFormat {
+  /// e.g. `3 hours ago`
+  #[default]
+  Relative,
+  /// RFC 3339 with the local UTC offset
+  Iso,
+  /// Local time like `Sun Jul  8 00:34:59 2001` (`%c` in the C
+  /// locale, whatever the system locale is)
+  Local,
+  /// Seconds since the epoch
+  Unix,
+}
+
+impl FromStr for DateFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "relative" => Ok(Self::Relative),
+      "iso" => Ok(Self::Iso),
+      "local" => Ok(Self::Local),
+      "unix" => Ok(Self::Unix),
+      _ => Err(
+        "expected \"relative\", \"iso\", \"local\" or \"unix\""
+          .to_string(),
+      ),
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct PutConfig {
+  /// Ask before trashing more than this many items at once
+  pub confirm_threshold: Option<usize>,
+  pub conflict: ConflictPolicy,
+  /// Largest size in bytes a trash may grow to before its oldest
+  /// entries are purged
+  pub quota: Option<u64>,
+}
+
+/// What to do when an entry with the same name is already in the
+/// trash.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ConflictPolicy {
+  /// Keep both, naming the new one `name(N).ext`
+  #[default]
+  Rename,
+  /// Permanently delete older entries trashed from the same path
+  Replace,
+}
+
+impl FromStr for ConflictPolicy {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "rename" => Ok(Self::Rename),
+      "replace" => Ok(Self::Replace),
+      _ => Err("expected \"rename\" or \"replace\"".to_string()),
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct EmptyConfig {
+  /// Only purge entries deleted at least this many days ago
+  pub retention_days: Option<u64>,
+}
+
+impl ListConfig {
+  fn apply(&mut self, table: &Table) -> Result<(), String> {
+    for (key, value) in table {
+      let name = format!("list.{}", key);
+      match key.as_str() {
+        "format" => self.format = choice(&name, value)?,
+        "sort" => self.sort = choice(&name, value)?,
+        "date" => self.date = choice(&name, value)?,
+        _ => return Err(format!("unknown key `{}`", name)),
+      }
+    }
+    Ok(())
+  }
+}
+
+impl PutConfig {
+  fn apply(&mut self, table: &Table) -> Result<(), String> {
+    for (key, value) in table {
+      let name = format!("put.{}", key);
+      match key.as_str() {
+        "confirm_threshold" => {
+          self.confirm_threshold =
+            Some(integer(&name, value)? as usize)
+        }
+        "conflict" => self.conflict = choice(&name, value)?,
+        "quota" => {
+          let quota = match value.as_str() {
+            Some(size) => parse_size(size)
+              .map_err(|e| format!("`{}`: {}", name, e))?,
+            None => integer(&name, value)?,
+          };
+          self.quota = Some(quota);
+        }
+        _ => return Err(format!("unknown key `{}`", name)),
+      }
+    }
+    Ok(())
+  }
+}
+
+impl EmptyConfig {
+  fn apply(&mut self, table: &Table) -> Result<(), String> {
+    for (key, value) in table {
+      let name = format!("empty.{}", key);
+      match key.as_str() {
+        "retention_days" => {
+          self.retention_days = Some(integer(&name, value)?)
+        }
+        _ => return Err(format!("unknown key `{}`", name)),
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Parse a byte count with an optional K, M, G or T suffix (powers
+/// of 1024), e.g. `512M`.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+  let size = size.trim();
+  let (digits, shift) = match size.char_indices().last() {
+    Some((i, 'K' | 'k')) => (&size[..i], 10),
+    Some((i, 'M' | 'm')) => (&size[..i], 20),
+    Some((i, 'G' | 'g')) => (&size[..i], 30),
+    Some((i, 'T' | 't')) => (&size[..i], 40),
+    _ => (size, 0),
+  };
+  digits
+    .trim()
+    .parse::<u64>()
+    .ok()
+    .and_then(|n| n.checked_mul(1 << shift))
+    .ok_or_else(|| format!("invalid size \"{}\"", size))
+}
+
+/// Which mounts are scanned for topdir trash directories.
+#[derive(Debug)]
+pub struct FilesystemPolicy {
+  /// Filesystem types to skip, on top of the built-in list
+  pub exclude: Vec<String>,
+  /// Filesystem types to scan even if otherwise skipped
+  pub include: Vec<String>,
+  pub skip_network: bool,
+  pub skip_read_only: bool,
+}
+
+impl Default for FilesystemPolicy {
+  fn default() -> Self {
+    Self {
+      exclude: PSEUDO_FILESYSTEMS
+        .iter()
+        .map(|s| s.to_string())
+        .collect(),
+      include: Vec::new(),
+      skip_network: true,
+      skip_read_only: false,
+    }
+  }
+}
+
+impl FilesystemPolicy {
+  pub fn skips(&self, fs_type: &str, read_only: bool) -> bool {
+    if self.include.iter().any(|p| fs_type_matches(p, fs_type)) {
+      return false;
+    }
+    if self.skip_read_only && read_only {
+      return true;
+    }
+    if self.skip_network
+      && NETWORK_FILESYSTEMS
+        .iter()
+        .any(|p| fs_type_matches(p, fs_type))
+    {
+      return true;
+    }
+    self.exclude.iter().any(|p| fs_type_matches(p, fs_type))
+  }
+
+  fn apply(&mut self, table: &Table) -> Result<(), String> {
+    for (key, value) in table {
+      let name = format!("filesystems.{}", key);
+      match key.as_str() {
+        "exclude" => self.exclude.extend(string_list(&name, value)?),
+        "include" => self.include = string_list(&name, value)?,
+        "skip_network" => self.skip_network = boolean(&name, value)?,
+        "skip_read_only" => {
+          self.skip_read_only = boolean(&name, value)?
+        }
+        _ => return Err(format!("unknown key `{}`", name)),
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Paths that must not be trashed casually, e.g. when `can` is
+/// aliased as `rm`.
+#[derive(Debug, Default)]
+pub struct ProtectPolicy {
+  /// Globs matched against absolute paths. `~` is the home
+  /// directory, `*` and `?` match within a path component and `**`
+  /// matches any number of components.
+  pub paths: Vec<String>,
+  pub action: ProtectAction,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub enum ProtectAction {
+  #[default]
+  Refuse,
+  Confirm,
+}
+
+impl FromStr for ProtectAction {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "refuse" => Ok(Self::Refuse),
+      "confirm" => Ok(Self::Confirm),
+      _ => Err("expected \"refuse\" or \"confirm\"".to_string()),
+    }
+  }
+}
+
+impl ProtectPolicy {
+  /// The first protected path at or under `path`, with the pattern
+  /// it matches. Trashing `~` would take `~/.ssh` with it, so
+  /// directories are searched wherever a pattern could still match.
+  pub fn matching(&self, path: &Path) -> Option<(PathBuf, &str)> {
+    if self.paths.is_empty() {
+      return None;
+    }
+    let home = env::var_os("HOME")
+      .map(|home| fs::canonicalize(&home).unwrap_or(home.into()));
+    let patterns: Vec<(Vec<u8>, &str)> = self
+      .paths
+      .iter()
+      .map(|p| (expand_home(p, home.as_deref()), p.as_str()))
+      .collect();
+    let patterns: Vec<(Vec<&[u8]>, &str)> = patterns
+      .iter()
+      .map(|(expanded, p)| (components(expanded), *p))
+      .collect();
+
+    protected_at_or_below(path, &patterns)
+  }
+
+  fn apply(&mut self, table: &Table) -> Result<(), String> {
+    for (key, value) in table {
+      let name = format!("protect.{}", key);
+      match key.as_str() {
+        "paths" => self.paths = string_list(&name, value)?,
+        "action" => self.action = choice(&name, value)?,
+        _ => return Err(format!("unknown key `{}`", name)),
+      }
+    }
+    Ok(())
+  }
+}
+
+fn protected_at_or_below<'a>(
+  path: &Path,
+  patterns: &[(Vec<&[u8]>, &'a str)],
+) -> Option<(PathBuf, &'a str)> {
+  let parts = components(path.as_os_str().as_bytes());
+  if let Some((_, pattern)) =
+    patterns.iter().find(|(p, _)| glob_match(p, &parts))
+  {
+    return Some((path.to_path_buf(), pattern));
+  }
+
+  // Don't follow symlinks: trashing one leaves its target alone
+  let is_dir = fs::symlink_metadata(path).is_ok_and(|m| m.is_dir());
+  if !is_dir || !patterns.iter().any(|(p, _)| glob_below(p, &parts)) {
+    return None;
+  }
+  fs::read_dir(path)
+    .ok()?
+    .flatten()
+    .find_map(|entry| protected_at_or_below(&entry.path(), patterns))
+}
+
+fn expand_home(pattern: &str, home: Option<&Path>) -> Vec<u8> {
+  match (pattern.strip_prefix('~'), home) {
+    (Some(rest), Some(home))
+      if rest.is_empty() || rest.starts_with('/') =>
+    {
+      let mut expanded = home.as_os_str().as_bytes().to_vec();
+      expanded.extend_from_slice(rest.as_bytes());
+      expanded
+    }
+    _ => pattern.as_bytes().to_vec(),
+  }
+}
+
+fn components(path: &[u8]) -> Vec<&[u8]> {
+  path
+    .split(|&b| b == b'/')
+    .filter(|c| !c.is_empty())
+    .collect()
+}
+
+/// Match path components against glob components, where `**`
+/// stands for zero or more whole components.
+fn glob_match(pattern: &[&[u8]], path: &[&[u8]]) -> bool {
+  match pattern.split_first() {
+    None => path.is_empty(),
+    Some((&b"**", rest)) => {
+      (0..=path.len()).any(|skip| glob_match(rest, &path[skip..]))
+    }
+    Some((first, rest)) => match path.split_first() {
+      Some((name, path_rest)) => {
+        wildcard_match(first, name) && glob_match(rest, path_rest)
+      }
+      None => false,
+    },
+  }
+}
+
+/// Whether something strictly below `path` could match `pattern`.
+fn glob_below(pattern: &[&[u8]], path: &[&[u8]]) -> bool {
+  match (pattern.split_first(), path.split_first()) {
+    (Some((&b"**", _)), _) => true,
+    (Some(_), None) => true,
+    (None, _) => false,
+    (Some((first, rest)), Some((name, path_rest))) => {
+      wildcard_match(first, name) && glob_below(rest, path_rest)
+    }
+  }
+}
+
+/// Match a single component where `*` is any run of bytes and `?`
+/// any one byte.
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+  match pattern.split_first() {
+    None => name.is_empty(),
+    Some((b'*', rest)) => {
+      (0..=name.len()).any(|skip| wildcard_match(rest, &name[skip..]))
+    }
+    Some((&p, rest)) => match name.split_first() {
+      Some((&n, name_rest)) => {
+        (p == b'?' || p == n) && wildcard_match(rest, name_rest)
+      }
+      None => false,
+    },
+  }
+}
+
+fn fs_type_matches(pattern: &str, fs_type: &str) -> bool {
+  match pattern.strip_suffix('*') {
+    Some(prefix) => fs_type.starts_with(prefix),
+    None => pattern == fs_type,
+  }
+}
+
+impl Config {
+  /// Load `$XDG_CONFIG_HOME/can/config.toml`, falling back to the
+  /// defaults when it doesn't exist. An unreadable or invalid file
+  /// is an error rather than being silently ignored.
+  pub fn load() -> Self {
+    let Some(path) = config_path() else {
+      return Self::default();
+    };
+    let content = match fs::read_to_string(&path) {
+      Ok(content) => content,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {
+        return Self::default();
+      }
+      Err(e) => fail!("can: {}: {}", path.display(), e),
+    };
+    Self::parse(&content)
+      .unwrap_or_else(|e| fail!("can: {}: {}", path.display(), e))
+  }
+
+  fn parse(content: &str) -> Result<Self, String> {
+    let table: Table =
+      content.parse().map_err(|e| format!("{}", e))?;
+    let mut config = Self::default();
+
+    for (key, value) in &table {
+      match key.as_str() {
+        "verbose" => config.verbose = boolean(key, value)?,
+        "color" => config.color = choice(key, value)?,
+        "list" => config.list.apply(section(key, value)?)?,
+        "put" => config.put.apply(section(key, value)?)?,
+        "empty" => config.empty.apply(section(key, value)?)?,
+        "filesystems" => {
+          config.filesystems.apply(section(key, value)?)?;
+        }
+        "protect" => config.protect.apply(section(key, value)?)?,
+        _ => return Err(format!("unknown key `{}`", key)),
+      }
+    }
+
+    Ok(config)
+  }
+}
+
+pub fn config_path() -> Option<PathBuf> {
+  BaseDirectories::with_prefix("can").get_config_file("config.toml")
+}
+
+fn section<'a>(
+  name: &str,
+  value: &'a Value,
+) -> Result<&'a Table, String> {
+  value
+    .as_table()
+    .ok_or_else(|| format!("`{}` must be a table", name))
+}
+
+fn boolean(name: &str, value: &Value) -> Result<bool, String> {
+  value
+    .as_bool()
+    .ok_or_else(|| format!("`{}` must be true or false", name))
+}
+
+fn integer(name: &str, value: &Value) -> Result<u64, String> {
+  value
+    .as_integer()
+    .and_then(|i| u64::try_from(i).ok())
+    .ok_or_else(|| {
+      format!("`{}` must be a non-negative integer", name)
+    })
+}
+
+fn choice<T: FromStr<Err = String>>(
+  name: &str,
+  value: &Value,
+) -> Result<T, String> {
+  value
+    .as_str()
+    .ok_or_else(|| "expected a string".to_string())
+    .and_then(str::parse)
+    .map_err(|e| format!("`{}`: {}", name, e))
+}
+
+fn string_list(
+  name: &str,
+  value: &Value,
+) -> Result<Vec<String>, String> {
+  let error = || format!("`{}` must be a list of strings", name);
+  value
+    .as_array()
+    .ok_or_else(error)?
+    .iter()
+    .map(|v| v.as_str().map(|s| s.to_string()).ok_or_else(error))
+    .collect()
+}
@@ -1,8 +1,10 @@
+/// Print an error to stderr, in red when stderr is colored, and
+/// exit. Errors are shown even when quiet.
 #[macro_export]
 macro_rules! fail {
   ($($vars:expr),*) => {
     {
-      println!($($vars),*);
+      eprintln!("{}", $crate::color::warning(format!($($vars),*)));
       process::exit(1);
     }
   };
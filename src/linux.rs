@@ -1,35 +1,36 @@
+use crate::config::{
+  Config, ConflictPolicy, FilesystemPolicy, PutConfig,
+};
 use crate::fail;
-use crate::shared::{get_all_trash_paths, get_home_trash_path};
-use chrono;
-use filetime;
-use libc;
+use crate::shared::{
+  get_all_trash_paths, get_home_trash_path, get_trash_entries,
+  path_exists, TrashEntry,
+};
+use crate::{debug, info, warn};
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::env;
+use std::ffi::{CString, OsStr, OsString};
 use std::fs;
 use std::io::{self};
 use std::io::{BufWriter, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::FileExt;
+use std::os::unix::fs::{symlink, DirBuilderExt, MetadataExt};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::process;
 use std::time::SystemTime;
-use urlencoding::{decode, encode};
+use urlencoding::{decode_binary, encode_binary};
 
-pub fn empty_trash(verbose: bool) {
-  let mut trash_paths = get_all_trash_paths();
+pub fn empty_trash(config: &Config, verbose: bool) {
+  let mut trash_paths = get_all_trash_paths(config, verbose);
   // Deduplicate paths (home trash may also be listed as a
   // per-device trash).
-  trash_paths.sort_by_key(|p| {
-    p.as_os_str()
-      .to_os_string()
-      .into_string()
-      .unwrap_or_default()
-  });
-  trash_paths.dedup_by_key(|p| {
-    p.as_os_str()
-      .to_os_string()
-      .into_string()
-      .unwrap_or_default()
-  });
+  trash_paths.sort();
+  trash_paths.dedup();
 
   let mut had_errors = false;
 
@@ -37,55 +38,41 @@ pub fn empty_trash(verbose: bool) {
     let files_dir = trash_path.join("files");
     let info_dir = trash_path.join("info");
 
-    if files_dir.exists() {
+    if path_exists(&files_dir) {
       // Recursively remove all files and directories
       if let Ok(entries) = fs::read_dir(&files_dir) {
-        for entry in entries {
-          if let Ok(entry) = entry {
-            let path = entry.path();
-            let result = if path.is_dir() {
-              fs::remove_dir_all(&path)
-            } else {
-              fs::remove_file(&path)
-            };
-
-            match result {
-              Ok(_) => {}
-              Err(e) => {
-                if verbose {
-                  eprintln!(
-                    "Warning: Failed to remove {}: {}",
-                    path.display(),
-                    e
-                  );
-                }
-                had_errors = true;
-              }
+        for entry in entries.flatten() {
+          let path = entry.path();
+          match remove_path(&path) {
+            Ok(_) => {}
+            Err(e) => {
+              warn!(
+                "Warning: Failed to remove {}: {}",
+                path.display(),
+                e
+              );
+              had_errors = true;
             }
           }
         }
       }
     }
 
-    if info_dir.exists() {
+    if path_exists(&info_dir) {
       // Remove all trashinfo files
       if let Ok(entries) = fs::read_dir(&info_dir) {
-        for entry in entries {
-          if let Ok(entry) = entry {
-            let path = entry.path();
-            if path.is_file() {
-              match fs::remove_file(&path) {
-                Ok(_) => {}
-                Err(e) => {
-                  if verbose {
-                    eprintln!(
-                      "Warning: Failed to remove {}: {}",
-                      path.display(),
-                      e
-                    );
-                  }
-                  had_errors = true;
-                }
+        for entry in entries.flatten() {
+          let path = entry.path();
+          if path.is_file() {
+            match fs::remove_file(&path) {
+              Ok(_) => {}
+              Err(e) => {
+                warn!(
+                  "Warning: Failed to remove {}: {}",
+                  path.display(),
+                  e
+                );
+                had_errors = true;
               }
             }
           }
@@ -95,16 +82,14 @@ pub fn empty_trash(verbose: bool) {
 
     // Remove directorysizes cache if it exists
     let sizes_path = trash_path.join("directorysizes");
-    if sizes_path.exists() {
+    if path_exists(&sizes_path) {
       match fs::remove_file(&sizes_path) {
         Ok(_) => {}
         Err(e) => {
-          if verbose {
-            eprintln!(
-              "Warning: Failed to remove directorysizes cache: {}",
-              e
-            );
-          }
+          warn!(
+            "Warning: Failed to remove directorysizes cache: {}",
+            e
+          );
           had_errors = true;
         }
       }
@@ -112,15 +97,120 @@ pub fn empty_trash(verbose: bool) {
   }
 
   if had_errors {
-    eprintln!("Warning: Some items could not be removed from trash");
+    warn!("Warning: Some items could not be removed from trash");
+  }
+
+  if !had_errors {
+    info!("Trash emptied");
+  }
+}
+
+/// A mounted filesystem as seen from our mount namespace.
+struct Mount {
+  /// `major:minor` of the backing device
+  device: String,
+  /// Directory of the filesystem that is mounted here
+  root: PathBuf,
+  mount_point: PathBuf,
+  fs_type: String,
+  read_only: bool,
+}
+
+/// The mounts worth scanning for trashes. A mount stacked on top of
+/// another at the same point hides it, and bind mounts of the same
+/// directory on the same device are collapsed into the first one
+/// listed.
+fn read_mounts() -> Vec<Mount> {
+  let mut mounts: Vec<Mount> = Vec::new();
+  for mount in read_mount_table() {
+    mounts.retain(|m| m.mount_point != mount.mount_point);
+    mounts.push(mount);
   }
 
-  if verbose || !had_errors {
-    println!("Trash emptied");
+  let mut seen = HashSet::new();
+  mounts.retain(|m| seen.insert((m.device.clone(), m.root.clone())));
+  mounts
+}
+
+/// Read our mount table from `/proc/self/mountinfo`, which reflects
+/// the process's mount namespace (so containers see their own
+/// mounts), keeping every entry.
+fn read_mount_table() -> Vec<Mount> {
+  let mut mounts = Vec::new();
+
+  let Ok(mountinfo) = fs::read_to_string("/proc/self/mountinfo")
+  else {
+    return mounts;
+  };
+
+  for line in mountinfo.lines() {
+    // id parent major:minor root mount-point options [optional...]
+    // - fs-type source super-options
+    let Some((left, right)) = line.split_once(" - ") else {
+      continue;
+    };
+    let fields: Vec<&str> = left.split(' ').collect();
+    let Some(fs_type) = right.split(' ').next() else {
+      continue;
+    };
+    if fields.len() < 6 {
+      continue;
+    }
+
+    let mount = Mount {
+      device: fields[2].to_string(),
+      root: PathBuf::from(unescape_mount_field(fields[3])),
+      mount_point: PathBuf::from(unescape_mount_field(fields[4])),
+      fs_type: unescape_mount_field(fs_type)
+        .to_string_lossy()
+        .into_owned(),
+      read_only: fields[5].split(',').any(|o| o == "ro"),
+    };
+
+    mounts.push(mount);
+  }
+
+  mounts
+}
+
+/// Every mount point, including bind mounts that `read_mounts`
+/// collapses.
+pub fn get_mount_points() -> Vec<PathBuf> {
+  read_mount_table()
+    .into_iter()
+    .map(|m| m.mount_point)
+    .collect()
+}
+
+/// Undo the kernel's octal escaping of spaces, tabs, newlines and
+/// backslashes (e.g. `\040`) in mount table fields.
+fn unescape_mount_field(field: &str) -> OsString {
+  let bytes = field.as_bytes();
+  let mut out = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let octal = bytes.get(i + 1..i + 4).and_then(|digits| {
+      let digits = std::str::from_utf8(digits).ok()?;
+      u8::from_str_radix(digits, 8).ok()
+    });
+    match (bytes[i], octal) {
+      (b'\\', Some(byte)) => {
+        out.push(byte);
+        i += 4;
+      }
+      (byte, _) => {
+        out.push(byte);
+        i += 1;
+      }
+    }
   }
+  OsString::from_vec(out)
 }
 
-pub fn get_topdir_trash_paths() -> Vec<PathBuf> {
+pub fn get_topdir_trash_paths(
+  policy: &FilesystemPolicy,
+  verbose: bool,
+) -> Vec<PathBuf> {
   let mut trash_paths = Vec::new();
   let uid = get_current_uid();
   // Only applicable on Linux
@@ -128,82 +218,150 @@ pub fn get_topdir_trash_paths() -> Vec<PathBuf> {
     return trash_paths;
   }
 
-  // Get all mount points
-  if let Ok(mounts) = fs::read_to_string("/proc/mounts") {
-    for line in mounts.lines() {
-      let parts: Vec<&str> = line.split_whitespace().collect();
-      if parts.len() >= 2 {
-        let mount_point = PathBuf::from(parts[1]);
-        let fs_type = parts[2];
-
-        // Skip pseudo filesystems
-        if fs_type == "proc"
-          || fs_type == "sysfs"
-          || fs_type == "tmpfs"
-          || fs_type == "devpts"
-          || fs_type == "devtmpfs"
-          || fs_type == "cgroup"
-          || fs_type == "cgroup2"
-          || fs_type == "securityfs"
-          || fs_type == "pstore"
-          || fs_type == "bpf"
-          || fs_type == "tracefs"
-          || fs_type == "debugfs"
-          || fs_type == "hugetlbfs"
-          || fs_type == "mqueue"
-          || fs_type == "autofs"
-          || fs_type == "configfs"
-          || fs_type == "fusectl"
-          || fs_type == "selinuxfs"
-          || fs_type == "rpc_pipefs"
-          || fs_type == "binfmt_misc"
-        {
-          continue;
-        }
+  for mount in read_mounts() {
+    if policy.skips(&mount.fs_type, mount.read_only) {
+      debug!(
+        "Skipping {} ({} filesystem)",
+        mount.mount_point.display(),
+        mount.fs_type
+      );
+      continue;
+    }
 
-        // Try method (1): $topdir/.Trash/$uid
-        let trash_method1 =
-          mount_point.join(".Trash").join(&uid.to_string());
-        if trash_method1.exists()
-          && is_valid_trash_dir(&trash_method1)
-        {
-          trash_paths.push(trash_method1);
-          continue;
-        }
+    let mount_point = mount.mount_point;
 
-        // Try method (2): $topdir/.Trash-$uid
-        let trash_method2 =
-          mount_point.join(&format!(".Trash-{}", uid));
-        if trash_method2.exists() {
-          trash_paths.push(trash_method2);
-        }
-      }
+    // Try method (1): $topdir/.Trash/$uid
+    let trash_method1 =
+      mount_point.join(".Trash").join(uid.to_string());
+    if path_exists(&trash_method1)
+      && is_secure_admin_trash(&mount_point, verbose)
+      && is_valid_trash_dir(&trash_method1)
+    {
+      debug!("Using trash {}", trash_method1.display());
+      trash_paths.push(trash_method1);
+      continue;
+    }
+
+    // Try method (2): $topdir/.Trash-$uid
+    let trash_method2 = mount_point.join(format!(".Trash-{}", uid));
+    if is_own_trash_dir(&trash_method2, uid) {
+      debug!("Using trash {}", trash_method2.display());
+      trash_paths.push(trash_method2);
     }
   }
 
   trash_paths
 }
 
-fn get_trash_path_for_file(file_path: &Path) -> Option<PathBuf> {
+fn get_trash_path_for_file(
+  file_path: &Path,
+  verbose: bool,
+) -> Option<PathBuf> {
   let home_trash = get_home_trash_path();
   let uid = get_current_uid();
 
-  let canonical_path = file_path.canonicalize().ok()?;
-  let topdir = canonical_path.parent()?;
+  // The path is already absolute with its parent resolved, so
+  // canonicalizing it again would follow a trailing symlink
+  let parent = file_path.parent()?;
+  let file_dev = device_of(parent)?;
 
-  let trash_method1 = topdir.join(".Trash").join(&uid.to_string());
-  if trash_method1.exists() && is_valid_trash_dir(&trash_method1) {
+  // Files on the same device as the home trash always go there
+  if device_of(&home_trash) == Some(file_dev) {
+    return Some(home_trash);
+  }
+
+  let topdir = find_mount_point(parent, file_dev);
+
+  let trash_method1 = topdir.join(".Trash").join(uid.to_string());
+  if path_exists(&trash_method1)
+    && is_secure_admin_trash(&topdir, verbose)
+    && is_valid_trash_dir(&trash_method1)
+  {
     return Some(trash_method1);
   }
 
-  let trash_method2 = topdir.join(&format!(".Trash-{}", uid));
-  if trash_method2.exists() {
+  // Method (2): $topdir/.Trash-$uid, created on demand so items on
+  // other volumes stay there instead of being copied home
+  let trash_method2 = topdir.join(format!(".Trash-{}", uid));
+  let usable = match fs::symlink_metadata(&trash_method2) {
+    Ok(_) => is_own_trash_dir(&trash_method2, uid),
+    Err(_) => create_topdir_trash(&trash_method2),
+  };
+  if usable {
     return Some(trash_method2);
   }
+  debug!(
+    "Ignoring {}: not a directory owned by us",
+    trash_method2.display()
+  );
 
+  // Only copy across devices when the volume has no usable trash
   Some(home_trash)
 }
 
+/// Create a per-user topdir trash with the 0700 mode the spec
+/// requires. Fails on read-only or unwritable volumes.
+fn create_topdir_trash(trash_path: &Path) -> bool {
+  let mut builder = fs::DirBuilder::new();
+  builder.mode(0o700);
+  if builder.create(trash_path).is_err() {
+    return false;
+  }
+  builder.create(trash_path.join("files")).is_ok()
+    && builder.create(trash_path.join("info")).is_ok()
+}
+
+/// Device id of `path`, or of its nearest existing ancestor when
+/// `path` itself hasn't been created yet (e.g. a fresh home trash).
+fn device_of(path: &Path) -> Option<u64> {
+  path
+    .ancestors()
+    .find_map(|p| fs::metadata(p).ok())
+    .map(|m| m.dev())
+}
+
+/// Walk up from `dir` while the parent is still on device `dev`.
+/// The last directory reached is the mount point, i.e. `$topdir`.
+fn find_mount_point(dir: &Path, dev: u64) -> PathBuf {
+  let mut topdir = dir;
+  while let Some(parent) = topdir.parent() {
+    match fs::metadata(parent) {
+      Ok(m) if m.dev() == dev => topdir = parent,
+      _ => break,
+    }
+  }
+  topdir.to_path_buf()
+}
+
+/// The shared `$topdir/.Trash` may only be used when it is a real
+/// directory with the sticky bit set, so other users can't swap or
+/// remove our `$uid` subdirectory. Anything else is skipped.
+fn is_secure_admin_trash(topdir: &Path, verbose: bool) -> bool {
+  let admin_trash = topdir.join(".Trash");
+  let reason = match fs::symlink_metadata(&admin_trash) {
+    Err(_) => return false,
+    Ok(m) if m.file_type().is_symlink() => "it is a symbolic link",
+    Ok(m) if !m.is_dir() => "it is not a directory",
+    Ok(m) if m.mode() & 0o1000 == 0 => "the sticky bit is not set",
+    Ok(_) => return true,
+  };
+  if verbose {
+    warn!(
+      "Warning: Ignoring shared trash {}: {}",
+      admin_trash.display(),
+      reason
+    );
+  }
+  false
+}
+
+/// A `$topdir/.Trash-$uid` is only ours if it is a real directory
+/// we own; a symlink or file planted by someone else is skipped.
+fn is_own_trash_dir(trash_dir: &Path, uid: u32) -> bool {
+  fs::symlink_metadata(trash_dir)
+    .is_ok_and(|m| m.is_dir() && m.uid() == uid)
+}
+
 fn is_valid_trash_dir(trash_dir: &Path) -> bool {
   let files_dir = trash_dir.join("files");
   let info_dir = trash_dir.join("info");
@@ -216,110 +374,356 @@ fn get_current_uid() -> u32 {
 }
 
 fn copy_file_to_trash(source: &Path, dest: &Path) -> io::Result<()> {
-  if source.is_dir() {
-    fs::create_dir_all(dest)?;
+  let mut links = HashMap::new();
+  copy_entry(source, dest, &mut links)
+}
 
-    // Preserve directory metadata
-    if let Ok(metadata) = fs::metadata(source) {
-      let _ = fs::set_permissions(dest, metadata.permissions());
-      if let Ok(times) = metadata.modified() {
-        let _ = filetime::set_file_mtime(
-          dest,
-          filetime::FileTime::from_system_time(times),
-        );
-      }
-      if let Ok(times) = metadata.accessed() {
-        let _ = filetime::set_file_atime(
-          dest,
-          filetime::FileTime::from_system_time(times),
-        );
-      }
+/// Copy one entry of a tree being trashed. `links` maps the
+/// (device, inode) of already copied multiply-linked files to their
+/// copy, so hard-link groups are recreated instead of duplicated.
+fn copy_entry(
+  source: &Path,
+  dest: &Path,
+  links: &mut HashMap<(u64, u64), PathBuf>,
+) -> io::Result<()> {
+  // Inspect the entry itself, never what a symlink points to
+  let metadata = fs::symlink_metadata(source)?;
+  let file_type = metadata.file_type();
+
+  if !file_type.is_dir() && metadata.nlink() > 1 {
+    let key = (metadata.dev(), metadata.ino());
+    if let Some(first) = links.get(&key) {
+      return fs::hard_link(first, dest)
+        .map_err(|e| copy_error(source, dest, e));
+    }
+    links.insert(key, dest.to_path_buf());
+  }
+
+  if file_type.is_symlink() {
+    // Recreate the link rather than copying its target
+    let target = fs::read_link(source)?;
+    if let Err(e) = symlink(&target, dest) {
+      return Err(copy_error(source, dest, e));
     }
+  } else if file_type.is_dir() {
+    fs::create_dir_all(dest)?;
 
     for entry in fs::read_dir(source)? {
       let entry = entry?;
-      let src = entry.path();
-      let dst = dest.join(entry.file_name());
+      copy_entry(
+        &entry.path(),
+        &dest.join(entry.file_name()),
+        links,
+      )?;
+    }
+  } else if file_type.is_file() {
+    if let Err(e) = copy_file_contents(source, dest) {
+      return Err(copy_error(source, dest, e));
+    }
+  } else {
+    // FIFOs, sockets and device nodes are recreated with mknod
+    if let Err(e) = make_node(dest, &metadata) {
+      return Err(copy_error(source, dest, e));
+    }
+  }
 
-      if src.is_dir() {
-        copy_file_to_trash(&src, &dst)?;
-      } else {
-        // Use copy with proper error handling
-        if let Err(e) = fs::copy(&src, &dst) {
-          return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!(
-              "Failed to copy {} to {}: {}",
-              src.display(),
-              dst.display(),
-              e
-            ),
-          ));
-        }
+  // Preserve metadata last; for directories this happens once the
+  // contents are in place, otherwise creating children would bump
+  // the mtime again
+  preserve_metadata(source, dest, &metadata);
+  Ok(())
+}
 
-        // Preserve file metadata
-        if let Ok(metadata) = fs::metadata(src) {
-          let _ = fs::set_permissions(&dst, metadata.permissions());
-          if let Ok(times) = metadata.modified() {
-            let _ = filetime::set_file_mtime(
-              &dst,
-              filetime::FileTime::from_system_time(times),
-            );
-          }
-          if let Ok(times) = metadata.accessed() {
-            let _ = filetime::set_file_atime(
-              &dst,
-              filetime::FileTime::from_system_time(times),
-            );
-          }
-        }
+/// Copy a regular file's data, preferring a reflink, then in-kernel
+/// `copy_file_range`, and skipping holes so sparse files stay sparse.
+#[cfg(target_os = "linux")]
+fn copy_file_contents(source: &Path, dest: &Path) -> io::Result<()> {
+  let src = fs::File::open(source)?;
+  let dst = fs::OpenOptions::new()
+    .write(true)
+    .create_new(true)
+    .open(dest)?;
+
+  // On btrfs/XFS a reflink shares extents and completes instantly
+  let res = unsafe {
+    libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd())
+  };
+  if res == 0 {
+    return Ok(());
+  }
+
+  let len = src.metadata()?.len();
+  let mut offset = 0u64;
+  while offset < len {
+    let data = unsafe {
+      libc::lseek(
+        src.as_raw_fd(),
+        offset as libc::off_t,
+        libc::SEEK_DATA,
+      )
+    };
+    if data < 0 {
+      let err = io::Error::last_os_error();
+      if err.raw_os_error() != Some(libc::ENXIO) {
+        // No SEEK_DATA support, so treat the rest as data
+        copy_range(&src, &dst, offset, len)?;
       }
+      // ENXIO means only a trailing hole remains
+      break;
     }
-  } else {
-    // Use copy with proper error handling
-    if let Err(e) = fs::copy(source, dest) {
-      return Err(io::Error::new(
-        io::ErrorKind::Other,
-        format!(
-          "Failed to copy {} to {}: {}",
-          source.display(),
-          dest.display(),
-          e
-        ),
-      ));
-    }
-
-    // Preserve file metadata
-    if let Ok(metadata) = fs::metadata(source) {
-      let _ = fs::set_permissions(dest, metadata.permissions());
-      if let Ok(times) = metadata.modified() {
-        let _ = filetime::set_file_mtime(
-          dest,
-          filetime::FileTime::from_system_time(times),
-        );
-      }
-      if let Ok(times) = metadata.accessed() {
-        let _ = filetime::set_file_atime(
-          dest,
-          filetime::FileTime::from_system_time(times),
-        );
-      }
+    let hole =
+      unsafe { libc::lseek(src.as_raw_fd(), data, libc::SEEK_HOLE) };
+    let end = if hole < 0 {
+      len
+    } else {
+      (hole as u64).min(len)
+    };
+    copy_range(&src, &dst, data as u64, end)?;
+    offset = end;
+  }
+
+  // Extend the copy over any trailing hole
+  dst.set_len(len)?;
+  Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_file_contents(source: &Path, dest: &Path) -> io::Result<()> {
+  let mut src = fs::File::open(source)?;
+  let mut dst = fs::OpenOptions::new()
+    .write(true)
+    .create_new(true)
+    .open(dest)?;
+  io::copy(&mut src, &mut dst)?;
+  Ok(())
+}
+
+/// Copy bytes `start..end` to the same offsets in `dst`.
+#[cfg(target_os = "linux")]
+fn copy_range(
+  src: &fs::File,
+  dst: &fs::File,
+  start: u64,
+  end: u64,
+) -> io::Result<()> {
+  let mut off_in = start as libc::loff_t;
+  let mut off_out = start as libc::loff_t;
+  while (off_in as u64) < end {
+    let n = unsafe {
+      libc::copy_file_range(
+        src.as_raw_fd(),
+        &mut off_in,
+        dst.as_raw_fd(),
+        &mut off_out,
+        (end - off_in as u64) as usize,
+        0,
+      )
+    };
+    if n < 0 {
+      let err = io::Error::last_os_error();
+      return match err.raw_os_error() {
+        // Unsupported for this pair of filesystems or kernel
+        Some(
+          libc::EXDEV
+          | libc::ENOSYS
+          | libc::EOPNOTSUPP
+          | libc::EINVAL,
+        ) => copy_range_buffered(src, dst, off_in as u64, end),
+        _ => Err(err),
+      };
+    }
+    if n == 0 {
+      // The source shrank underneath us
+      break;
     }
   }
   Ok(())
 }
 
-pub fn move_file_to_trash(files: &[String]) {
-  for file_path in files {
-    let source_path = Path::new(file_path);
+#[cfg(target_os = "linux")]
+fn copy_range_buffered(
+  src: &fs::File,
+  dst: &fs::File,
+  start: u64,
+  end: u64,
+) -> io::Result<()> {
+  let mut buf = vec![0u8; 128 * 1024];
+  let mut offset = start;
+  while offset < end {
+    let want = ((end - offset) as usize).min(buf.len());
+    let n = src.read_at(&mut buf[..want], offset)?;
+    if n == 0 {
+      break;
+    }
+    dst.write_all_at(&buf[..n], offset)?;
+    offset += n as u64;
+  }
+  Ok(())
+}
 
-    // Determine which trash directory to use
-    let trash_path = match get_trash_path_for_file(source_path) {
-      Some(path) => path,
-      None => {
-        fail!("can: Could not determine trash path for {}", file_path)
-      }
+fn copy_error(source: &Path, dest: &Path, e: io::Error) -> io::Error {
+  io::Error::other(format!(
+    "Failed to copy {} to {}: {}",
+    source.display(),
+    dest.display(),
+    e
+  ))
+}
+
+fn make_node(dest: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+  let path = path_to_cstring(dest)?;
+  let res = unsafe {
+    libc::mknod(
+      path.as_ptr(),
+      metadata.mode() as libc::mode_t,
+      metadata.rdev() as libc::dev_t,
+    )
+  };
+  if res != 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+/// Best-effort copy of ownership, permissions, extended attributes
+/// and times. Ownership only carries over when we are permitted to
+/// chown, which is usually only as root.
+fn preserve_metadata(
+  source: &Path,
+  dest: &Path,
+  metadata: &fs::Metadata,
+) {
+  let is_symlink = metadata.file_type().is_symlink();
+
+  // chown clears setuid/setgid bits, so it must precede chmod
+  if let Ok(path) = path_to_cstring(dest) {
+    unsafe {
+      libc::lchown(path.as_ptr(), metadata.uid(), metadata.gid());
+    }
+  }
+  if !is_symlink {
+    let _ = fs::set_permissions(dest, metadata.permissions());
+  }
+
+  // Covers SELinux labels and POSIX ACLs, which are stored as
+  // security.* and system.posix_acl_* attributes
+  copy_xattrs(source, dest);
+
+  let _ = filetime::set_symlink_file_times(
+    dest,
+    filetime::FileTime::from_last_access_time(metadata),
+    filetime::FileTime::from_last_modification_time(metadata),
+  );
+}
+
+#[cfg(target_os = "linux")]
+fn copy_xattrs(source: &Path, dest: &Path) {
+  let (Ok(src), Ok(dst)) =
+    (path_to_cstring(source), path_to_cstring(dest))
+  else {
+    return;
+  };
+
+  let size = unsafe {
+    libc::llistxattr(src.as_ptr(), std::ptr::null_mut(), 0)
+  };
+  if size <= 0 {
+    return;
+  }
+  let mut names = vec![0u8; size as usize];
+  let size = unsafe {
+    libc::llistxattr(
+      src.as_ptr(),
+      names.as_mut_ptr() as *mut libc::c_char,
+      names.len(),
+    )
+  };
+  if size <= 0 {
+    return;
+  }
+  names.truncate(size as usize);
+
+  for name in names.split(|&b| b == 0).filter(|n| !n.is_empty()) {
+    let Ok(name) = CString::new(name) else {
+      continue;
     };
+    let size = unsafe {
+      libc::lgetxattr(
+        src.as_ptr(),
+        name.as_ptr(),
+        std::ptr::null_mut(),
+        0,
+      )
+    };
+    if size < 0 {
+      continue;
+    }
+    let mut value = vec![0u8; size as usize];
+    let size = unsafe {
+      libc::lgetxattr(
+        src.as_ptr(),
+        name.as_ptr(),
+        value.as_mut_ptr() as *mut libc::c_void,
+        value.len(),
+      )
+    };
+    if size < 0 {
+      continue;
+    }
+    // Attributes the destination refuses (e.g. user.* on symlinks
+    // or security.* without privileges) are skipped
+    unsafe {
+      libc::lsetxattr(
+        dst.as_ptr(),
+        name.as_ptr(),
+        value.as_ptr() as *const libc::c_void,
+        size as usize,
+        0,
+      );
+    }
+  }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_xattrs(_source: &Path, _dest: &Path) {}
+
+fn path_to_cstring(path: &Path) -> io::Result<CString> {
+  CString::new(path.as_os_str().as_bytes())
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Remove a file, symlink or directory tree without following
+/// symlinks.
+fn remove_path(path: &Path) -> io::Result<()> {
+  if fs::symlink_metadata(path)?.is_dir() {
+    fs::remove_dir_all(path)
+  } else {
+    fs::remove_file(path)
+  }
+}
+
+pub fn move_file_to_trash(
+  files: &[PathBuf],
+  put: &PutConfig,
+  verbose: bool,
+) {
+  // Names given out in each trash, spared by quota enforcement
+  let mut trashed: Vec<(PathBuf, Vec<OsString>)> = Vec::new();
+
+  for source_path in files {
+    let file_path = source_path.display();
+
+    // Determine which trash directory to use
+    let trash_path =
+      match get_trash_path_for_file(source_path, verbose) {
+        Some(path) => path,
+        None => {
+          fail!(
+            "can: Could not determine trash path for {}",
+            file_path
+          )
+        }
+      };
 
     // Create trash directories if they don't exist
     create_trash_directories(&trash_path);
@@ -327,128 +731,382 @@ pub fn move_file_to_trash(files: &[String]) {
     let files_dir = trash_path.join("files");
     let info_dir = trash_path.join("info");
 
-    let file_name = source_path
-      .file_name()
-      .and_then(|s| Some(s.to_string_lossy().to_string()))
-      .unwrap_or_else(|| {
-        fail!("can: Invalid file path for {}", file_path);
-      });
-
-    // Find unique name, checking both files/ and info/ directories
-    let dest_name =
-      find_unique_name(&files_dir, &info_dir, &file_name);
+    let file_name = source_path.file_name().unwrap_or_else(|| {
+      fail!("can: Invalid file path for {}", file_path);
+    });
+
+    // Reserve the name by creating the trashinfo before the move
+    let info_content = trashinfo_content(source_path, &trash_path);
+    let dest_name = match reserve_trash_name(
+      &files_dir,
+      &info_dir,
+      file_name,
+      &info_content,
+    ) {
+      Ok(name) => name,
+      Err(err) => fail!("can: Failed to create trashinfo: {}", err),
+    };
     let dest_path = files_dir.join(&dest_name);
-    let info_path = info_dir.join(format!("{}.trashinfo", dest_name));
+    let info_path = info_dir.join(trashinfo_name(&dest_name));
+
+    // A symlink to a directory is trashed as a link, not a directory
+    let is_dir = fs::symlink_metadata(source_path)
+      .map(|m| m.is_dir())
+      .unwrap_or(false);
+
+    match fs::rename(source_path, &dest_path) {
+      Ok(()) => {}
+      Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+        move_across_devices(
+          source_path,
+          &dest_path,
+          &info_path,
+          &trash_path,
+          is_dir,
+        );
+      }
+      Err(e) => {
+        // Release the reserved name
+        let _ = fs::remove_file(&info_path);
+        fail!("can: Failed to move {} to trash: {}", file_path, e)
+      }
+    }
+    debug!("Moved {} to {}", file_path, dest_path.display());
 
-    // Ensure no name collision before we touch the filesystem
-    if info_path.exists() || dest_path.exists() {
-      fail!("can: Trash collision for {}", file_path);
+    // Update directory‑sizes cache for moved directories
+    if is_dir {
+      update_directorysizes_cache(&trash_path, &dest_name);
     }
 
-    // Move the file (or copy+remove on cross-fs rename failure)
-    let move_res = fs::rename(source_path, &dest_path);
-    if let Err(_rename_err) = move_res {
-      // Cross‑filesystem: copy then delete the original
-      if let Err(e) = copy_file_to_trash(source_path, &dest_path) {
-        fail!("can: Failed to move {} to trash: {}", file_path, e);
+    // Only once the new payload is safely in the trash
+    if put.conflict == ConflictPolicy::Replace {
+      remove_older_versions(&trash_path, source_path, &dest_name);
+    }
+
+    match trashed.iter_mut().find(|(t, _)| *t == trash_path) {
+      Some((_, names)) => names.push(dest_name),
+      None => trashed.push((trash_path, vec![dest_name])),
+    }
+  }
+
+  if let Some(quota) = put.quota {
+    for (trash_path, names) in &trashed {
+      enforce_quota(trash_path, quota, names);
+    }
+  }
+}
+
+/// Purge entries of the trash at `trash_path` trashed from
+/// `original_path`, other than the one just trashed as `keep`.
+fn remove_older_versions(
+  trash_path: &Path,
+  original_path: &Path,
+  keep: &OsStr,
+) {
+  for entry in get_trash_entries(trash_path) {
+    if entry.original_path != original_path || entry.name == keep {
+      continue;
+    }
+    match entry.remove() {
+      Ok(()) => info!("Replaced {}", entry.path.display()),
+      Err(e) => warn!(
+        "Warning: Failed to remove {}: {}",
+        entry.path.display(),
+        e
+      ),
+    }
+  }
+}
+
+/// Purge the oldest entries of the trash at `trash_path` until it
+/// fits in `quota` bytes. Entries named in `keep` are never purged,
+/// and when they alone exceed the quota nothing else is either.
+fn enforce_quota(trash_path: &Path, quota: u64, keep: &[OsString]) {
+  let mut entries: Vec<_> = get_trash_entries(trash_path)
+    .into_iter()
+    .map(|entry| (entry.size(), entry))
+    .collect();
+  let mut total: u64 = entries.iter().map(|(size, _)| size).sum();
+  if total <= quota {
+    return;
+  }
+
+  let kept: u64 = entries
+    .iter()
+    .filter(|(_, entry)| keep.contains(&entry.name))
+    .map(|(size, _)| size)
+    .sum();
+  if kept > quota {
+    warn!(
+      "Warning: Items just trashed exceed the quota of {} bytes for {}",
+      quota,
+      trash_path.display()
+    );
+    return;
+  }
+
+  entries
+    .sort_by(|(_, a), (_, b)| a.deletion_date.cmp(&b.deletion_date));
+  for (size, entry) in entries {
+    if total <= quota {
+      return;
+    }
+    if keep.contains(&entry.name) {
+      continue;
+    }
+    match entry.remove() {
+      Ok(()) => {
+        total -= size;
+        info!("Purged {} to stay within quota", entry.path.display());
       }
-      let remove_res = if source_path.is_dir() {
-        fs::remove_dir_all(source_path)
-      } else {
-        fs::remove_file(source_path)
-      };
-      if let Err(e) = remove_res {
-        // Cleanup the partially copied file/directory
-        let _ = if dest_path.is_dir() {
-          fs::remove_dir_all(&dest_path)
-        } else {
-          fs::remove_file(&dest_path)
-        };
-        fail!(
-          "can: Failed to remove original path {}: {}",
-          file_path,
-          e
-        );
+      Err(e) => warn!(
+        "Warning: Failed to remove {}: {}",
+        entry.path.display(),
+        e
+      ),
+    }
+  }
+
+  if total > quota {
+    warn!(
+      "Warning: {} is over its quota of {} bytes",
+      trash_path.display(),
+      quota
+    );
+  }
+}
+
+/// Copy `source` into the trash on another filesystem, then remove
+/// the original. The copy is staged under a hidden name in `files/`
+/// and only renamed into place once the original is gone, so a
+/// crash at any point leaves either the original or a complete
+/// copy, and never a partial tree posing as a trash entry. The
+/// trashinfo at `info_path` must already have been reserved.
+fn move_across_devices(
+  source_path: &Path,
+  dest_path: &Path,
+  info_path: &Path,
+  trash_path: &Path,
+  is_dir: bool,
+) {
+  let file_path = source_path.display();
+  let staging_path = staging_path(dest_path);
+
+  if let Err(e) = copy_file_to_trash(source_path, &staging_path) {
+    let _ = remove_path(&staging_path);
+    let _ = fs::remove_file(info_path);
+    fail!("can: Failed to move {} to trash: {}", file_path, e);
+  }
+
+  // Make sure the copy and its trashinfo are on disk before the
+  // original goes away
+  sync_filesystem(trash_path);
+
+  if let Err(e) = remove_path(source_path) {
+    if !is_dir {
+      // Removing a single file is all or nothing, so undo the copy
+      let _ = remove_path(&staging_path);
+      let _ = fs::remove_file(info_path);
+      fail!(
+        "can: Failed to remove original path {}: {}",
+        file_path,
+        e
+      );
+    }
+
+    // A directory may be partly removed by now; keep the complete
+    // copy rather than lose what's already gone
+    let _ = fs::rename(&staging_path, dest_path);
+    fail!(
+      "can: Failed to fully remove original path {}: {} (a complete copy was kept in the trash)",
+      file_path,
+      e
+    );
+  }
+
+  if let Err(e) = fs::rename(&staging_path, dest_path) {
+    fail!(
+      "can: Failed to move {} into place: {}",
+      staging_path.display(),
+      e
+    );
+  }
+}
+
+/// Move a trash entry back to its original path and drop its
+/// trashinfo. Whatever is at the original path now is never
+/// replaced.
+pub fn restore_entry(entry: &TrashEntry) -> io::Result<()> {
+  let dest_path = &entry.original_path;
+  if let Some(parent) = dest_path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+
+  match rename_noreplace(&entry.path, dest_path) {
+    Ok(()) => {}
+    Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+      let staging_path = staging_path(dest_path);
+      if let Err(e) = copy_file_to_trash(&entry.path, &staging_path) {
+        let _ = remove_path(&staging_path);
+        return Err(e);
       }
+      if let Err(e) = rename_noreplace(&staging_path, dest_path) {
+        let _ = remove_path(&staging_path);
+        return Err(e);
+      }
+      remove_path(&entry.path)?;
     }
+    Err(e) => return Err(e),
+  }
 
-    // Create .trashinfo file atomically after move succeeds
-    if let Err(err) =
-      create_atomic_trashinfo(&info_path, source_path, &trash_path)
-    {
-      // If we cannot write the metadata, roll back the moved file
-      let _ = if dest_path.is_dir() {
-        fs::remove_dir_all(&dest_path)
-      } else {
-        fs::remove_file(&dest_path)
-      };
-      fail!("can: Failed to create trashinfo: {}", err);
+  fs::remove_file(&entry.info_path)
+}
+
+/// Rename `from` to `to`, failing if `to` already exists.
+#[cfg(target_os = "linux")]
+fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+  let from_c = path_to_cstring(from)?;
+  let to_c = path_to_cstring(to)?;
+  let res = unsafe {
+    libc::renameat2(
+      libc::AT_FDCWD,
+      from_c.as_ptr(),
+      libc::AT_FDCWD,
+      to_c.as_ptr(),
+      libc::RENAME_NOREPLACE,
+    )
+  };
+  if res == 0 {
+    return Ok(());
+  }
+  let err = io::Error::last_os_error();
+  match err.raw_os_error() {
+    // Not every filesystem supports RENAME_NOREPLACE
+    Some(libc::EINVAL) | Some(libc::ENOSYS) => {
+      rename_checked(from, to)
     }
+    _ => Err(err),
+  }
+}
 
-    // Update directory‑sizes cache for moved directories
-    if source_path.is_dir() {
-      update_directorysizes_cache(&trash_path, &dest_name);
+#[cfg(not(target_os = "linux"))]
+fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+  rename_checked(from, to)
+}
+
+fn rename_checked(from: &Path, to: &Path) -> io::Result<()> {
+  if path_exists(to) {
+    return Err(io::ErrorKind::AlreadyExists.into());
+  }
+  fs::rename(from, to)
+}
+
+/// Hidden sibling of `dest_path` used while a copy is in progress.
+fn staging_path(dest_path: &Path) -> PathBuf {
+  let mut name =
+    OsString::from(format!(".can-staging-{}-", process::id()));
+  if let Some(file_name) = dest_path.file_name() {
+    name.push(file_name);
+  }
+  dest_path.with_file_name(name)
+}
+
+#[cfg(target_os = "linux")]
+fn sync_filesystem(path: &Path) {
+  if let Ok(dir) = fs::File::open(path) {
+    unsafe {
+      libc::syncfs(dir.as_raw_fd());
     }
   }
 }
 
+#[cfg(not(target_os = "linux"))]
+fn sync_filesystem(_path: &Path) {
+  unsafe {
+    libc::sync();
+  }
+}
+
 fn create_trash_directories(trash_path: &Path) {
   let files_dir = trash_path.join("files");
   let info_dir = trash_path.join("info");
 
-  if !trash_path.exists() {
-    fs::create_dir_all(trash_path)
-      .expect("Failed to create trash directory");
-  }
-  if !files_dir.exists() {
-    fs::create_dir_all(&files_dir)
-      .expect("Failed to create trash files directory");
-  }
-  if !info_dir.exists() {
-    fs::create_dir_all(&info_dir)
-      .expect("Failed to create trash info directory");
+  for dir in [trash_path, &files_dir, &info_dir] {
+    if let Err(e) = fs::create_dir_all(dir) {
+      fail!(
+        "can: Failed to create trash directory {}: {}",
+        dir.display(),
+        e
+      );
+    }
   }
 }
 
-fn find_unique_name(
+/// Reserve a name in the trash by exclusively creating its
+/// `.trashinfo`, as the spec requires. On a clash the next
+/// `name(N).ext` is tried, so concurrent processes can never end up
+/// with the same name.
+fn reserve_trash_name(
   files_dir: &Path,
   info_dir: &Path,
-  original_name: &str,
-) -> String {
-  let mut name = original_name.to_string();
+  original_name: &OsStr,
+  info_content: &str,
+) -> io::Result<OsString> {
+  let mut name = original_name.to_os_string();
   let mut counter = 1;
 
-  loop {
-    let test_files_path = files_dir.join(&name);
-    let test_info_path =
-      info_dir.join(&format!("{}.trashinfo", name));
-
-    if !test_files_path.exists() && !test_info_path.exists() {
-      return name;
-    }
+  // Split on raw bytes so names that aren't valid UTF-8 keep
+  // their exact encoding.
+  let bytes = original_name.as_bytes();
+  let (base_name, extension) =
+    match bytes.iter().rposition(|&b| b == b'.') {
+      Some(pos) => bytes.split_at(pos),
+      None => (bytes, &b""[..]),
+    };
 
-    let extension_pos = original_name.rfind('.');
-    let (base_name, extension) = match extension_pos {
-      Some(pos) => {
-        let base = &original_name[..pos];
-        let ext = &original_name[pos..];
-        (base.to_string(), ext.to_string())
+  loop {
+    // Names already used by a payload without trashinfo are skipped
+    if !path_exists(&files_dir.join(&name)) {
+      let info_path = info_dir.join(trashinfo_name(&name));
+      match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&info_path)
+      {
+        Ok(file) => {
+          let mut writer = BufWriter::new(file);
+          let res = writer
+            .write_all(info_content.as_bytes())
+            .and_then(|_| writer.flush());
+          if let Err(e) = res {
+            let _ = fs::remove_file(&info_path);
+            return Err(e);
+          }
+          return Ok(name);
+        }
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
       }
-      None => (original_name.to_string(), String::new()),
-    };
+    }
 
-    name = format!("{}({}){}", base_name, counter, extension);
+    let mut next = base_name.to_vec();
+    next.extend_from_slice(format!("({})", counter).as_bytes());
+    next.extend_from_slice(extension);
+    name = OsString::from_vec(next);
     counter += 1;
   }
 }
 
-fn create_atomic_trashinfo(
-  info_path: &Path,
+fn trashinfo_name(name: &OsStr) -> OsString {
+  let mut info_name = name.to_os_string();
+  info_name.push(".trashinfo");
+  info_name
+}
+
+fn trashinfo_content(
   original_path: &Path,
   trash_path: &Path,
-) -> io::Result<()> {
-  let temp_path = info_path.with_extension("trashinfo.tmp");
-
+) -> String {
   // Determine if this is the home trash directory
   let home_trash = get_home_trash_path();
   let is_home_trash = trash_path == home_trash;
@@ -460,85 +1118,60 @@ fn create_atomic_trashinfo(
       if stripped.as_os_str().is_empty()
         || stripped.as_os_str() == "."
       {
-        original_path.display().to_string()
+        original_path.as_os_str()
       } else {
-        stripped.display().to_string()
+        stripped.as_os_str()
       }
     } else {
-      original_path.display().to_string()
+      original_path.as_os_str()
     }
   } else {
     // For topdir trash, use absolute paths as per spec
-    original_path.display().to_string()
+    original_path.as_os_str()
   };
 
-  // URL encode the path as required by the spec
-  let encoded_path = encode(&original_path_str);
+  // URL encode the raw path bytes as required by the spec
+  let encoded_path = encode_binary(original_path_str.as_bytes());
 
   let deletion_date = chrono::Local::now();
   let deletion_date_str =
     deletion_date.format("%Y-%m-%dT%H:%M:%S").to_string();
 
-  let info_content = format!(
+  format!(
     "[Trash Info]\nPath={}\nDeletionDate={}\n",
     encoded_path, deletion_date_str
-  );
-
-  // Ensure parent directory exists
-  if let Some(parent_dir) = info_path.parent() {
-    fs::create_dir_all(parent_dir)?;
-  }
-
-  // Create temp file with exclusive access
-  let file = fs::OpenOptions::new()
-    .write(true)
-    .create_new(true)
-    .open(&temp_path)?;
-
-  let mut writer = BufWriter::new(file);
-
-  writer.write_all(info_content.as_bytes())?;
-  writer.flush()?;
-
-  // Atomic rename
-  fs::rename(&temp_path, info_path)?;
-  Ok(())
+  )
 }
 
-fn update_directorysizes_cache(trash_path: &Path, dir_name: &str) {
+fn update_directorysizes_cache(trash_path: &Path, dir_name: &OsStr) {
   let cache_path = trash_path.join("directorysizes");
   let dir_path = trash_path.join("files").join(dir_name);
 
   if let Ok(dir_size) = calculate_directory_size(&dir_path) {
     let mtime = get_trashinfo_mtime(trash_path, dir_name);
 
-    // Read existing cache
-    let mut cache_entries: HashMap<String, (u64, i64)> =
+    // Read existing cache, keyed by the decoded (raw byte) name
+    let mut cache_entries: HashMap<OsString, (u64, i64)> =
       HashMap::new();
-    let mut cache_names: HashMap<String, String> = HashMap::new(); // Store URL-encoded names
     if let Ok(content) = fs::read_to_string(&cache_path) {
       for line in content.lines() {
         let mut iter = line.splitn(3, ' ');
         let size = iter.next().and_then(|s| s.parse::<u64>().ok());
         let mtime = iter.next().and_then(|s| s.parse::<i64>().ok());
-        let encoded_name = iter.next().map(|s| s.to_string());
+        let encoded_name = iter.next();
         if let (Some(size), Some(mtime), Some(encoded_name)) =
           (size, mtime, encoded_name)
         {
-          let decoded_name =
-            decode(&encoded_name).expect("Failed to decode path");
-          cache_entries
-            .insert(decoded_name.to_string(), (size, mtime));
-          cache_names.insert(decoded_name.to_string(), encoded_name); // Keep the original encoded form
+          let decoded_name = OsString::from_vec(
+            decode_binary(encoded_name.as_bytes()).into_owned(),
+          );
+          cache_entries.insert(decoded_name, (size, mtime));
         }
       }
     }
 
-    // Update entry (URL encode the directory name as per spec)
-    let encoded_dir_name = encode(dir_name);
-    cache_entries.insert(dir_name.to_string(), (dir_size, mtime));
-    cache_names
-      .insert(dir_name.to_string(), encoded_dir_name.to_string());
+    // Update entry
+    cache_entries.insert(dir_name.to_os_string(), (dir_size, mtime));
 
     // Write to temp then rename using buffered writer
     let temp_path = cache_path.with_extension("tmp");
@@ -549,26 +1182,18 @@ fn update_directorysizes_cache(trash_path: &Path, dir_name: &str) {
       let files_dir = trash_path.join("files");
       let mut seen_entries = HashSet::new();
       if let Ok(entries) = fs::read_dir(&files_dir) {
-        for entry in entries {
-          if let Ok(entry) = entry {
-            let entry_name = entry.file_name();
-            let name_str = entry_name.to_string_lossy();
-            if let Ok(decoded_name) = decode(&name_str) {
-              seen_entries.insert(decoded_name.to_string());
-            }
-          }
+        for entry in entries.flatten() {
+          seen_entries.insert(entry.file_name());
         }
       }
 
       // Second pass: write entries, removing unseen ones per spec's algorithm
       for (name, (size, mtime)) in cache_entries {
         if seen_entries.contains(&name) {
-          // Use the URL-encoded name from cache_names
-          let encoded_name = cache_names.get(&name).unwrap_or(&name);
+          // URL encode the directory name as per spec
+          let encoded_name = encode_binary(name.as_bytes());
           let line = format!("{} {} {}\n", size, mtime, encoded_name);
-          if let Err(e) = writer.write_all(line.as_bytes()) {
-            return Err(e);
-          }
+          writer.write_all(line.as_bytes())?;
         }
         // If not seen, the entry will be removed from cache (as per spec)
       }
@@ -577,45 +1202,42 @@ fn update_directorysizes_cache(trash_path: &Path, dir_name: &str) {
     }) {
       Ok(_) => {
         if let Err(e) = fs::rename(&temp_path, &cache_path) {
-          eprintln!(
-            "Warning: failed to update directorysizes: {}",
-            e
-          );
+          warn!("Warning: failed to update directorysizes: {}", e);
           let _ = fs::remove_file(&temp_path); // Clean up temp file
         }
       }
       Err(e) => {
-        eprintln!("Warning: failed to write directorysizes: {}", e);
+        warn!("Warning: failed to write directorysizes: {}", e);
         let _ = fs::remove_file(&temp_path); // Clean up temp file on error
       }
     }
   }
 }
 
-fn calculate_directory_size(dir_path: &Path) -> io::Result<u64> {
+pub fn calculate_directory_size(dir_path: &Path) -> io::Result<u64> {
   let mut total_size = 0u64;
 
   if let Ok(entries) = fs::read_dir(dir_path) {
-    for entry in entries {
-      if let Ok(entry) = entry {
-        let path = entry.path();
-        if path.is_dir() {
-          match calculate_directory_size(&path) {
-            Ok(size) => total_size += size,
-            Err(e) => {
-              // Log the error but continue with other entries
-              eprintln!(
-                "Warning: Failed to calculate size of {}: {}",
-                path.display(),
-                e
-              );
-            }
-          }
-        } else {
-          if let Ok(metadata) = fs::metadata(&path) {
-            total_size += metadata.len();
+    for entry in entries.flatten() {
+      let path = entry.path();
+      // Count symlinks as themselves rather than their targets
+      let Ok(metadata) = fs::symlink_metadata(&path) else {
+        continue;
+      };
+      if metadata.is_dir() {
+        match calculate_directory_size(&path) {
+          Ok(size) => total_size += size,
+          Err(e) => {
+            // Log the error but continue with other entries
+            warn!(
+              "Warning: Failed to calculate size of {}: {}",
+              path.display(),
+              e
+            );
           }
         }
+      } else {
+        total_size += metadata.len();
       }
     }
   }
@@ -623,10 +1245,9 @@ fn calculate_directory_size(dir_path: &Path) -> io::Result<u64> {
   Ok(total_size)
 }
 
-fn get_trashinfo_mtime(trash_path: &Path, dir_name: &str) -> i64 {
-  let info_path = trash_path
-    .join("info")
-    .join(format!("{}.trashinfo", dir_name));
+fn get_trashinfo_mtime(trash_path: &Path, dir_name: &OsStr) -> i64 {
+  let info_path =
+    trash_path.join("info").join(trashinfo_name(dir_name));
   if let Ok(metadata) = fs::metadata(&info_path) {
     if let Ok(modified) = metadata.modified() {
       // Convert to seconds since UNIX epoch
@@ -0,0 +1,65 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How much is said on stderr besides errors. Stdout only ever
+/// carries what was asked for.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+  /// Errors only (`-q`)
+  Quiet,
+  /// Warnings and status messages
+  Normal,
+  /// What was done, item by item (`-v`)
+  Verbose,
+  /// Which trashes are used and why (`-vv`)
+  Debug,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Normal as u8);
+
+pub fn init(level: Level) {
+  LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+  level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Print a warning to stderr, in red when stderr is colored.
+#[macro_export]
+macro_rules! warn {
+  ($($vars:expr),*) => {
+    if $crate::log::enabled($crate::log::Level::Normal) {
+      eprintln!("{}", $crate::color::warning(format!($($vars),*)))
+    }
+  };
+}
+
+/// Print a status message to stderr unless quiet.
+#[macro_export]
+macro_rules! status {
+  ($($vars:expr),*) => {
+    if $crate::log::enabled($crate::log::Level::Normal) {
+      eprintln!($($vars),*)
+    }
+  };
+}
+
+/// Print a message to stderr when verbose.
+#[macro_export]
+macro_rules! info {
+  ($($vars:expr),*) => {
+    if $crate::log::enabled($crate::log::Level::Verbose) {
+      eprintln!($($vars),*)
+    }
+  };
+}
+
+/// Print a message to stderr at `-vv`.
+#[macro_export]
+macro_rules! debug {
+  ($($vars:expr),*) => {
+    if $crate::log::enabled($crate::log::Level::Debug) {
+      eprintln!($($vars),*)
+    }
+  };
+}
@@ -1,17 +1,21 @@
 use crate::fail;
+use crate::info;
+use std::path::PathBuf;
 use std::process;
 use std::process::Command;
 use std::str::from_utf8;
 
-pub fn empty_trash(verbose: bool) {
+pub fn empty_trash() {
   let as_cmd = "tell application \"Finder\" to empty trash";
   let res = run_applescript(as_cmd.to_string());
-  if res.is_ok() && verbose {
-    println!("Trash emptied");
+  if res.is_ok() {
+    info!("Trash emptied");
   }
 }
 
-pub fn move_file_to_trash(files: &[String]) {
+pub fn move_file_to_trash(files: &[PathBuf]) {
+  let files: Vec<String> =
+    files.iter().map(|f| f.display().to_string()).collect();
   let mut as_list = "{ POSIX file \"".to_owned();
   as_list.push_str(&files.join("\", POSIX file \""));
   as_list.push_str("\"}");
@@ -28,18 +32,14 @@ fn run_applescript(as_cmd: String) -> Result<String, String> {
   let res = Command::new("osascript").args(["-e", &as_cmd]).output();
   match res {
     Ok(output) => {
-      if output.stderr.len() > 0 {
+      if !output.stderr.is_empty() {
         let err = from_utf8(&output.stderr)
-          .unwrap_or_else(|_| "Unknown UTF-8 error".into())
+          .unwrap_or("Unknown UTF-8 error")
           .to_owned();
         return Err(err);
       }
-      return Ok(
-        from_utf8(&output.stdout)
-          .unwrap_or_else(|_| "".into())
-          .to_owned(),
-      );
+      Ok(from_utf8(&output.stdout).unwrap_or("").to_owned())
     }
-    Err(err) => return Err(err.to_string()),
+    Err(err) => Err(err.to_string()),
   }
 }
@@ -1,62 +1,663 @@
+use completions::Shell;
+use config::{parse_size, Config, ListFormat};
 use optz::{Opt, Optz};
 use shared::*;
 use std::env;
+use std::ffi::OsString;
 use std::process;
+use std::str::FromStr;
 
+mod color;
+mod completions;
+mod config;
 mod fail;
 mod linux;
+mod log;
 mod macos;
+mod man;
+mod picker;
 mod shared;
 
+/// A command line option. Kept separate from `optz::Opt` so help
+/// can show the value each option takes.
+struct Flag {
+  name: &'static str,
+  short: Option<&'static str>,
+  /// Placeholder for the option's value, if it takes one
+  value: Option<&'static str>,
+  /// Every accepted value, when there's a fixed set
+  choices: &'static [&'static str],
+  description: &'static str,
+}
+
+impl Flag {
+  fn opt(&self) -> Opt {
+    let opt = match self.value {
+      Some(_) => Opt::arg(self.name),
+      // Flags may be repeated, e.g. `-vv`, so count them all
+      None => Opt::flag(self.name).multiple(true),
+    };
+    let opt = opt.description(self.description);
+    match self.short {
+      Some(short) => opt.short(short),
+      None => opt,
+    }
+  }
+
+  fn matches(&self, arg: &str) -> bool {
+    arg.strip_prefix("--") == Some(self.name)
+      || self.short == Some(arg)
+  }
+}
+
+struct Command {
+  name: &'static str,
+  /// Older option spellings that still select the command
+  aliases: &'static [&'static str],
+  /// Left out of help and completions
+  hidden: bool,
+  /// Shown in usage; in brackets when optional
+  operands: &'static str,
+  description: &'static str,
+  options: &'static [Flag],
+}
+
+/// Accepted by every command.
+const GLOBAL_OPTIONS: &[Flag] = &[
+  Flag {
+    name: "verbose",
+    short: Some("-v"),
+    value: None,
+    choices: &[],
+    description: "Run verbosely, -vv for more",
+  },
+  Flag {
+    name: "quiet",
+    short: Some("-q"),
+    value: None,
+    choices: &[],
+    description: "Only print errors",
+  },
+  Flag {
+    name: "color",
+    short: None,
+    value: Some("WHEN"),
+    choices: &["auto", "always", "never"],
+    description: "Color output: auto, always or never",
+  },
+];
+
+/// Handled before parsing; listed last in help.
+const HELP: Flag = Flag {
+  name: "help",
+  short: Some("-h"),
+  value: None,
+  choices: &[],
+  description: "Show help",
+};
+
+/// Only valid on its own, before any command.
+const MAN: Flag = Flag {
+  name: "man",
+  short: None,
+  value: None,
+  choices: &[],
+  description: "Print the manual page",
+};
+
+/// Accepted wherever items are looked up by original path.
+const NEWEST: Flag = Flag {
+  name: "newest",
+  short: None,
+  value: None,
+  choices: &[],
+  description: "Take the newest of several matching items",
+};
+
+/// Accepted wherever deletion dates are shown.
+const DATE: Flag = Flag {
+  name: "date",
+  short: None,
+  value: Some("FORMAT"),
+  choices: &["relative", "iso", "local", "unix"],
+  description: "Dates as relative, iso, local or unix",
+};
+
+/// The first command is the default when none is given.
+const COMMANDS: &[Command] = &[
+  Command {
+    name: "put",
+    aliases: &[],
+    hidden: false,
+    operands: "file ...",
+    description: "Move files to the trash",
+    options: &[
+      Flag {
+        name: "conflict",
+        short: None,
+        value: Some("POLICY"),
+        choices: &["rename", "replace"],
+        description: "On name clash in trash: rename or replace",
+      },
+      Flag {
+        name: "confirm-threshold",
+        short: None,
+        value: Some("N"),
+        choices: &[],
+        description: "Ask before trashing more than N items",
+      },
+      Flag {
+        name: "quota",
+        short: None,
+        value: Some("SIZE"),
+        choices: &[],
+        description: "Purge oldest items past SIZE (e.g. 10G, none)",
+      },
+      Flag {
+        name: "force-dangerous",
+        short: None,
+        value: None,
+        choices: &[],
+        description: "Allow trashing /, $HOME, mounts and trash",
+      },
+    ],
+  },
+  Command {
+    name: "list",
+    aliases: &["-l", "--list"],
+    hidden: false,
+    operands: "",
+    description: "List trash contents",
+    options: &[
+      Flag {
+        name: "format",
+        short: None,
+        value: Some("FORMAT"),
+        choices: &["short", "long"],
+        description: "List format: short or long",
+      },
+      Flag {
+        name: "sort",
+        short: None,
+        value: Some("KEY"),
+        choices: &["name", "date", "original"],
+        description: "Sort by name, date or original",
+      },
+      DATE,
+    ],
+  },
+  Command {
+    name: "restore",
+    aliases: &["--restore"],
+    hidden: false,
+    operands: "name|path ...",
+    description: "Move trashed items back to where they came from",
+    options: &[
+      Flag {
+        name: "interactive",
+        short: Some("-i"),
+        value: None,
+        choices: &[],
+        description: "Pick items in a full screen list",
+      },
+      NEWEST,
+    ],
+  },
+  Command {
+    name: "purge",
+    aliases: &["--purge"],
+    hidden: false,
+    operands: "name|path ...",
+    description: "Permanently delete trashed items",
+    options: &[NEWEST],
+  },
+  Command {
+    name: "cat",
+    aliases: &["--cat", "--show"],
+    hidden: false,
+    operands: "name ...",
+    description: "Print the contents of trashed files",
+    options: &[
+      Flag {
+        name: "binary",
+        short: None,
+        value: None,
+        choices: &[],
+        description: "Print binary files to a terminal too",
+      },
+      Flag {
+        name: "max-size",
+        short: None,
+        value: Some("SIZE"),
+        choices: &[],
+        description: "Stop after SIZE bytes (default 1M, or none)",
+      },
+    ],
+  },
+  Command {
+    name: "tree",
+    aliases: &["--tree"],
+    hidden: false,
+    operands: "name ...",
+    description: "Show the files inside trashed directories",
+    options: &[],
+  },
+  Command {
+    name: "versions",
+    aliases: &["--versions"],
+    hidden: false,
+    operands: "path ...",
+    description: "List every trashed version of a path",
+    options: &[
+      Flag {
+        name: "diff",
+        short: None,
+        value: None,
+        choices: &[],
+        description: "Compare each version with the current file",
+      },
+      DATE,
+    ],
+  },
+  Command {
+    name: "empty",
+    aliases: &["-E", "--empty"],
+    hidden: false,
+    operands: "",
+    description: "Permanently delete everything in the trash",
+    options: &[Flag {
+      name: "older-than",
+      short: None,
+      value: Some("DAYS"),
+      choices: &[],
+      description: "Only purge items trashed more than DAYS ago",
+    }],
+  },
+  Command {
+    name: "completions",
+    aliases: &[],
+    hidden: false,
+    operands: "shell",
+    description: "Print a bash, zsh or fish completion script",
+    options: &[],
+  },
+  Command {
+    name: "__complete-entries",
+    aliases: &[],
+    hidden: true,
+    operands: "[prefix]",
+    description: "Print the names of trashed items, for completion",
+    options: &[],
+  },
+];
+
 fn main() {
-  let optz = Optz::from_args("can", env::args().collect())
-    .option(
-      Opt::flag("verbose")
-        .short("-v")
-        .description("Run verbosely"),
-    )
-    .option(
-      Opt::flag("list")
-        .short("-l")
-        .description("List trash contents"),
-    )
-    .option(Opt::flag("empty").short("-E").description("Empty trash"))
-    .parse()
-    .unwrap();
-
-  let verbose = match optz.get::<bool>("verbose") {
-    Ok(Some(v)) => v,
-    Ok(None) => false,
-    Err(_) => false,
+  let mut args_os: Vec<OsString> = Vec::new();
+  let mut raw = env::args_os().skip(1);
+  for arg in raw.by_ref() {
+    if arg == "--" {
+      args_os.push(arg);
+      break;
+    }
+    args_os.extend(split_arg(arg));
+  }
+  args_os.extend(raw);
+
+  // Everything after `--` is a file name, even if it looks like an
+  // option or a command
+  let mut operands_os = Vec::new();
+  if let Some(pos) = args_os.iter().position(|a| a == "--") {
+    operands_os = args_os.split_off(pos + 1);
+    args_os.pop();
+  }
+
+  let mut args: Vec<String> = args_os
+    .iter()
+    .map(|a| a.to_string_lossy().into_owned())
+    .collect();
+
+  let command = match find_command(&args) {
+    Some((command, index)) => {
+      args.remove(index);
+      args_os.remove(index);
+      command
+    }
+    None => {
+      if args.iter().any(|arg| MAN.matches(arg)) {
+        print!("{}", man::page());
+        process::exit(0);
+      }
+      let no_files = operands_os.is_empty()
+        && args.iter().all(|arg| arg.starts_with('-'));
+      if no_files || args.iter().any(|arg| is_help(arg)) {
+        help();
+        process::exit(0);
+      }
+      &COMMANDS[0]
+    }
   };
 
-  if optz.has("list").unwrap_or(false) {
-    list(&optz, verbose);
+  if args.iter().any(|arg| is_help(arg)) {
+    command_help(command);
     process::exit(0);
   }
+  check_options(command, &args);
 
-  if optz.has("empty").unwrap_or(false) {
-    empty(&optz, verbose);
-    process::exit(0);
+  let name = format!("can {}", command.name);
+  let mut optz = Optz::from_args(
+    &name,
+    [name.clone()].into_iter().chain(args).collect(),
+  );
+  for flag in GLOBAL_OPTIONS.iter().chain(command.options) {
+    optz = optz.option(flag.opt());
   }
+  let optz =
+    optz.parse().unwrap_or_else(|e| fail!("{}: {}", name, e));
 
-  // Get file arguments (non-option arguments)
-  if !optz.rest.is_empty() {
-    move_files_to_trash(&optz, verbose);
+  let mut operands = rest_os(&optz.rest, &args_os);
+  operands.extend(operands_os);
+
+  if command.operands.is_empty() {
+    if let Some(operand) = operands.first() {
+      fail!(
+        "{}: Unexpected argument `{}`",
+        name,
+        operand.to_string_lossy()
+      );
+    }
+  } else if operands.is_empty()
+    && !command.operands.starts_with('[')
+    && !optz.has("interactive").unwrap_or(false)
+  {
+    fail!("{}: Missing operand (see `{} -h`)", name, name);
+  }
+
+  let mut config = Config::load();
+
+  // Command line options take precedence over the config file
+  let level = if optz.has("quiet").unwrap_or(false) {
+    log::Level::Quiet
   } else {
-    help(&optz);
+    match optz.get_values::<bool>("verbose").map(|v| v.len()) {
+      Ok(0) | Err(_) if !config.verbose => log::Level::Normal,
+      Ok(0 | 1) | Err(_) => log::Level::Verbose,
+      Ok(_) => log::Level::Debug,
+    }
+  };
+  log::init(level);
+  let verbose = level >= log::Level::Verbose;
+  if let Some(color) = option(&optz, "color") {
+    config.color = color;
+  }
+  color::init(config.color);
+  if verbose {
+    config.list.format = ListFormat::Long;
+  }
+  if let Some(format) = option(&optz, "format") {
+    config.list.format = format;
+  }
+  if let Some(sort) = option(&optz, "sort") {
+    config.list.sort = sort;
+  }
+  if let Some(date) = option(&optz, "date") {
+    config.list.date = date;
+  }
+  if let Some(days) = option(&optz, "older-than") {
+    config.empty.retention_days = Some(days);
+  }
+  if let Some(conflict) = option(&optz, "conflict") {
+    config.put.conflict = conflict;
+  }
+  if let Some(threshold) = option(&optz, "confirm-threshold") {
+    config.put.confirm_threshold = Some(threshold);
+  }
+  if let Some(quota) = option::<String>(&optz, "quota") {
+    config.put.quota = match quota.as_str() {
+      "none" => None,
+      size => Some(
+        parse_size(size)
+          .unwrap_or_else(|e| fail!("can: --quota: {}", e)),
+      ),
+    };
+  }
+
+  match command.name {
+    "list" => list(&optz, &config, verbose),
+    "restore" if optz.has("interactive").unwrap_or(false) => {
+      let filter: Vec<String> = operands
+        .iter()
+        .map(|o| o.to_string_lossy().into_owned())
+        .collect();
+      restore_interactive(&filter.join(" "), &config, verbose);
+    }
+    "restore" | "purge" => {
+      let newest = optz.has("newest").unwrap_or(false);
+      if command.name == "restore" {
+        restore(&operands, &config, verbose, newest);
+      } else {
+        purge(&operands, &config, verbose, newest);
+      }
+    }
+    "cat" => {
+      let max_size =
+        match option::<String>(&optz, "max-size").as_deref() {
+          None => Some(CAT_MAX_SIZE),
+          Some("none") => None,
+          Some(size) => Some(
+            parse_size(size)
+              .unwrap_or_else(|e| fail!("can: --max-size: {}", e)),
+          ),
+        };
+      let binary = optz.has("binary").unwrap_or(false);
+      cat(&operands, &config, verbose, binary, max_size);
+    }
+    "tree" => tree(&operands, &config, verbose),
+    "versions" => {
+      let diff = optz.has("diff").unwrap_or(false);
+      for operand in &operands {
+        versions(operand, &config, verbose, diff);
+      }
+    }
+    "empty" => empty(&optz, &config, verbose),
+    "completions" => {
+      let shell = operands[0].to_string_lossy();
+      match shell.parse::<Shell>() {
+        Ok(shell) => print!("{}", completions::script(shell)),
+        Err(e) => fail!("{}: {}", name, e),
+      }
+    }
+    "__complete-entries" => {
+      let prefix = operands.first().cloned().unwrap_or_default();
+      complete_entries(&prefix, &config);
+    }
+    _ => {
+      let force_dangerous =
+        optz.has("force-dangerous").unwrap_or(false);
+      move_files_to_trash(
+        &operands,
+        &config,
+        verbose,
+        force_dangerous,
+      );
+    }
   }
 }
 
-fn help(optz: &Optz) {
+/// Find the command selected by `args` and its position. Leading
+/// options are skipped; the first operand is a command only if it
+/// names one, otherwise it's a file for the default command.
+fn find_command(
+  args: &[String],
+) -> Option<(&'static Command, usize)> {
+  let mut i = 0;
+  while i < args.len() {
+    let arg = args[i].as_str();
+    if let Some(command) =
+      COMMANDS.iter().find(|c| c.aliases.contains(&arg))
+    {
+      return Some((command, i));
+    }
+    if !arg.starts_with('-') || arg == "-" {
+      let command = COMMANDS.iter().find(|c| c.name == arg)?;
+      return Some((command, i));
+    }
+    // Skip the value of any option that takes one
+    let takes_value = value_options().any(|flag| flag.matches(arg));
+    i += if takes_value { 2 } else { 1 };
+  }
+  None
+}
+
+/// Options that take a value, across all commands.
+fn value_options() -> impl Iterator<Item = &'static Flag> {
+  GLOBAL_OPTIONS
+    .iter()
+    .chain(COMMANDS.iter().flat_map(|c| c.options))
+    .filter(|flag| flag.value.is_some())
+}
+
+/// Split `--name=value` into two arguments when `name` is an
+/// option that takes a value, and a repeated short flag like `-vv`
+/// into one argument per repetition.
+fn split_arg(arg: OsString) -> Vec<OsString> {
+  let split = arg.to_str().and_then(|s| {
+    if let Some((name, value)) =
+      s.strip_prefix("--").and_then(|s| s.split_once('='))
+    {
+      return value_options()
+        .any(|flag| flag.name == name)
+        .then(|| vec![format!("--{}", name).into(), value.into()]);
+    }
+    let letters = s.strip_prefix('-')?;
+    let short = format!("-{}", letters.chars().next()?);
+    let repeated = letters.len() > 1
+      && letters.chars().all(|c| short.ends_with(c))
+      && GLOBAL_OPTIONS
+        .iter()
+        .chain(COMMANDS.iter().flat_map(|c| c.options))
+        .any(|flag| flag.value.is_none() && flag.matches(&short));
+    repeated.then(|| vec![short.into(); letters.len()])
+  });
+  split.unwrap_or_else(|| vec![arg])
+}
+
+/// Reject options `command` doesn't know rather than ignoring them.
+fn check_options(command: &Command, args: &[String]) {
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    if !arg.starts_with('-') || arg == "-" {
+      continue;
+    }
+    let flag = GLOBAL_OPTIONS
+      .iter()
+      .chain(command.options)
+      .find(|flag| flag.matches(arg));
+    match flag {
+      Some(flag) if flag.value.is_some() => {
+        if args.next().is_none() {
+          fail!(
+            "can {}: {} requires a {}",
+            command.name,
+            arg,
+            flag.value.unwrap_or("value")
+          );
+        }
+      }
+      Some(_) => {}
+      None => fail!(
+        "can {}: Unknown option `{}` (see `can {} -h`)",
+        command.name,
+        arg,
+        command.name
+      ),
+    }
+  }
+}
+
+fn is_help(arg: &str) -> bool {
+  HELP.matches(arg)
+}
+
+/// Parse the value of option `name`, if given, exiting with a
+/// message when it's invalid.
+fn option<T: FromStr>(optz: &Optz, name: &str) -> Option<T>
+where
+  T::Err: std::fmt::Display,
+{
+  let value = optz.get::<String>(name).ok().flatten()?;
+  match value.parse() {
+    Ok(value) => Some(value),
+    Err(e) => fail!("can: --{}: {}", name, e),
+  }
+}
+
+/// Map the lossy `rest` strings back to the raw OS arguments they
+/// came from so file names with invalid UTF-8 survive untouched.
+fn rest_os(rest: &[String], args_os: &[OsString]) -> Vec<OsString> {
+  let mut files = Vec::new();
+  let mut args_iter = args_os.iter();
+  for arg in rest {
+    match args_iter.find(|a| a.to_string_lossy() == arg.as_str()) {
+      Some(raw) => files.push(raw.clone()),
+      None => files.push(OsString::from(arg)),
+    }
+  }
+  files
+}
+
+fn help() {
   println!("Usage: can [options] file ...");
-  for opt in &optz.options {
-    let short_str = opt.short.as_deref().unwrap_or("");
-    println!(
-      "  {}, {:<10} {}",
-      short_str,
-      opt.long,
-      opt.description.as_deref().unwrap_or("")
-    )
+  println!("       can <command> [options] [args]");
+  println!();
+  println!("Commands:");
+  for (i, command) in COMMANDS.iter().enumerate() {
+    if command.hidden {
+      continue;
+    }
+    let mut description = command.description.to_string();
+    if i == 0 {
+      description.push_str(" (default)");
+    } else if !command.aliases.is_empty() {
+      description
+        .push_str(&format!(" ({})", command.aliases.join(", ")));
+    }
+    println!("  {:<11} {}", command.name, description);
+  }
+  println!();
+  println!("Options:");
+  print_options(
+    GLOBAL_OPTIONS
+      .iter()
+      .chain(COMMANDS[0].options)
+      .chain([&MAN]),
+  );
+  println!();
+  println!("Run `can <command> -h` for the options of a command.");
+}
+
+fn command_help(command: &Command) {
+  let usage = format!(
+    "Usage: can {} [options] {}",
+    command.name, command.operands
+  );
+  println!("{}", usage.trim_end());
+  println!();
+  println!("{}", command.description);
+  println!();
+  println!("Options:");
+  print_options(GLOBAL_OPTIONS.iter().chain(command.options));
+}
+
+fn print_options<'a>(flags: impl Iterator<Item = &'a Flag>) {
+  for flag in flags.chain([&HELP]) {
+    let short = match flag.short {
+      Some(short) => format!("{},", short),
+      None => String::new(),
+    };
+    let long = match flag.value {
+      Some(value) => format!("--{} {}", flag.name, value),
+      None => format!("--{}", flag.name),
+    };
+    println!("  {:<3} {:<23} {}", short, long, flag.description);
   }
 }
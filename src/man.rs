@@ -0,0 +1,163 @@
+use crate::{Flag, COMMANDS, GLOBAL_OPTIONS, HELP, MAN};
+
+const DESCRIPTION: &str = "can moves files to the trash as an alternative to rm. Trashed items can be listed, restored to where they came from or deleted permanently later. On Linux the FreeDesktop.org Trash specification is followed, so the trash is shared with desktop file managers; on macOS the Finder trash is used.";
+
+const EXIT_STATUS: &[(&str, &str)] = &[
+  ("0", "Success."),
+  (
+    "1",
+    "An error occurred, an argument was invalid or a confirmation was declined.",
+  ),
+];
+
+const ENVIRONMENT: &[(&str, &str)] = &[
+  (
+    "XDG_DATA_HOME",
+    "The home trash is $XDG_DATA_HOME/Trash. Defaults to ~/.local/share.",
+  ),
+  (
+    "XDG_CONFIG_HOME",
+    "The configuration file is $XDG_CONFIG_HOME/can/config.toml. Defaults to ~/.config.",
+  ),
+  (
+    "LS_COLORS",
+    "Colors for names by file type, as used by ls(1).",
+  ),
+  (
+    "NO_COLOR",
+    "When set and not empty, output is not colored unless --color always is given.",
+  ),
+  (
+    "HOME",
+    "Used for the defaults above, for ~ in protected paths and to refuse trashing the home directory.",
+  ),
+];
+
+const FILES: &[(&str, &str)] = &[
+  (
+    "$XDG_DATA_HOME/Trash/files/",
+    "Trashed files and directories of the home trash.",
+  ),
+  (
+    "$XDG_DATA_HOME/Trash/info/NAME.trashinfo",
+    "Original path and deletion date of the trashed item NAME. Written before the item is moved, and the item is not listed without it.",
+  ),
+  (
+    "$XDG_DATA_HOME/Trash/directorysizes",
+    "Cached sizes of trashed directories.",
+  ),
+  (
+    "$topdir/.Trash/$uid/, $topdir/.Trash-$uid/",
+    "Trashes for files on other mounts, where $topdir is the mount point. The first is used when the administrator created a sticky .Trash directory, otherwise the second is created as needed.",
+  ),
+  (
+    "$XDG_CONFIG_HOME/can/config.toml",
+    "Defaults for options, the filesystems scanned for trashes and protected paths.",
+  ),
+];
+
+/// The `can(1)` manual page in roff, generated from the same
+/// command and option tables the parser uses.
+pub fn page() -> String {
+  let mut out = format!(
+    ".TH CAN 1 \"\" \"can {}\" \"User Commands\"\n",
+    env!("CARGO_PKG_VERSION")
+  );
+  out.push_str(".SH NAME\ncan \\- move files to the trash\n");
+
+  out.push_str(".SH SYNOPSIS\n");
+  out.push_str(".B can\n[\\fIoptions\\fR] \\fIfile\\fR ...\n.br\n");
+  out.push_str(
+    ".B can\n\\fIcommand\\fR [\\fIoptions\\fR] [\\fIargs\\fR]\n",
+  );
+
+  out.push_str(".SH DESCRIPTION\n");
+  out.push_str(&escape(DESCRIPTION));
+  out.push_str("\n.PP\n");
+  out.push_str(&escape(&format!(
+    "Without a command, can {0} is assumed. Arguments after -- are always file names, so a file named like a command is trashed with can -- NAME or can {0} NAME.",
+    COMMANDS[0].name
+  )));
+  out.push('\n');
+
+  out.push_str(".SH COMMANDS\n");
+  for command in COMMANDS.iter().filter(|c| !c.hidden) {
+    out.push_str(&format!(".TP\n\\fB{}\\fR", escape(command.name)));
+    if !command.operands.is_empty() {
+      out.push_str(&format!(" \\fI{}\\fR", escape(command.operands)));
+    }
+    out.push('\n');
+    out.push_str(&escape(command.description));
+    out.push_str(".\n");
+    if !command.aliases.is_empty() {
+      out.push_str(&format!(
+        "Also selected by {}.\n",
+        command
+          .aliases
+          .iter()
+          .map(|a| format!("\\fB{}\\fR", escape(a)))
+          .collect::<Vec<_>>()
+          .join(", ")
+      ));
+    }
+    if !command.options.is_empty() {
+      out.push_str(".RS\n");
+      for flag in command.options {
+        out.push_str(&option(flag));
+      }
+      out.push_str(".RE\n");
+    }
+  }
+
+  out.push_str(".SH OPTIONS\n");
+  for flag in GLOBAL_OPTIONS.iter().chain([&MAN, &HELP]) {
+    out.push_str(&option(flag));
+  }
+
+  section(&mut out, "EXIT STATUS", EXIT_STATUS);
+  section(&mut out, "ENVIRONMENT", ENVIRONMENT);
+  section(&mut out, "FILES", FILES);
+
+  out.push_str(".SH SEE ALSO\n.BR rm (1)\n.PP\n");
+  out.push_str(
+    "https://specifications.freedesktop.org/trash-spec/latest/\n",
+  );
+  out
+}
+
+fn option(flag: &Flag) -> String {
+  let mut out = String::from(".TP\n");
+  if let Some(short) = flag.short {
+    out.push_str(&format!("\\fB{}\\fR, ", escape(short)));
+  }
+  out.push_str(&format!("\\fB\\-\\-{}\\fR", escape(flag.name)));
+  if let Some(value) = flag.value {
+    out.push_str(&format!(" \\fI{}\\fR", escape(value)));
+  }
+  out.push('\n');
+  out.push_str(&escape(flag.description));
+  out.push('\n');
+  out
+}
+
+fn section(out: &mut String, title: &str, items: &[(&str, &str)]) {
+  out.push_str(&format!(".SH {}\n", title));
+  for (term, text) in items {
+    out.push_str(&format!(
+      ".TP\n\\fB{}\\fR\n{}\n",
+      escape(term),
+      escape(text)
+    ));
+  }
+}
+
+/// Escape text for roff. Hyphens are escaped so options render as
+/// minus signs and can be searched for.
+fn escape(text: &str) -> String {
+  let text = text.replace('\\', "\\e").replace('-', "\\-");
+  if text.starts_with(['.', '\'']) {
+    format!("\\&{}", text)
+  } else {
+    text
+  }
+}
@@ -0,0 +1,468 @@
+use crate::config::DateFormat;
+use crate::shared::{format_size, TrashEntry};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+pub enum Action {
+  Restore,
+  Purge,
+}
+
+enum Key {
+  Char(char),
+  Backspace,
+  Up,
+  Down,
+  PageUp,
+  PageDown,
+  Tab,
+  Enter,
+  Escape,
+  Ctrl(u8),
+  Unknown,
+}
+
+/// The controlling terminal in raw mode on the alternate screen.
+/// Dropping it puts everything back. `/dev/tty` is used rather than
+/// stdin and stdout so the picker works inside pipes.
+struct Terminal {
+  tty: File,
+  saved: libc::termios,
+}
+
+impl Terminal {
+  fn open() -> io::Result<Self> {
+    let tty =
+      OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    let fd = tty.as_raw_fd();
+    let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut saved) } != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    let mut raw = saved;
+    unsafe { libc::cfmakeraw(&mut raw) };
+    if unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &raw) } != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    let mut terminal = Terminal { tty, saved };
+    terminal.tty.write_all(b"\x1b[?1049h")?;
+    Ok(terminal)
+  }
+
+  /// Columns and rows, falling back to 80x24.
+  fn size(&self) -> (usize, usize) {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let res = unsafe {
+      libc::ioctl(self.tty.as_raw_fd(), libc::TIOCGWINSZ, &mut size)
+    };
+    if res != 0 || size.ws_col == 0 || size.ws_row == 0 {
+      return (80, 24);
+    }
+    (size.ws_col as usize, size.ws_row as usize)
+  }
+
+  fn read_byte(&mut self) -> io::Result<u8> {
+    let mut byte = [0u8];
+    self.tty.read_exact(&mut byte)?;
+    Ok(byte[0])
+  }
+
+  /// Whether more input arrives within a few milliseconds, which
+  /// tells a lone Escape from the start of an escape sequence.
+  fn pending(&self) -> bool {
+    let mut fd = libc::pollfd {
+      fd: self.tty.as_raw_fd(),
+      events: libc::POLLIN,
+      revents: 0,
+    };
+    unsafe { libc::poll(&mut fd, 1, 50) > 0 }
+  }
+
+  fn read_key(&mut self) -> io::Result<Key> {
+    let key = match self.read_byte()? {
+      b'\r' | b'\n' => Key::Enter,
+      b'\t' => Key::Tab,
+      0x7f | 0x08 => Key::Backspace,
+      0x1b if !self.pending() => Key::Escape,
+      0x1b => match (self.read_byte()?, self.read_byte()?) {
+        (b'[' | b'O', b'A') => Key::Up,
+        (b'[' | b'O', b'B') => Key::Down,
+        (b'[', b'5') if self.read_byte()? == b'~' => Key::PageUp,
+        (b'[', b'6') if self.read_byte()? == b'~' => Key::PageDown,
+        _ => Key::Unknown,
+      },
+      byte @ 0x00..=0x1f => Key::Ctrl(byte + b'a' - 1),
+      byte if byte < 0x80 => Key::Char(byte as char),
+      byte => {
+        // Collect the rest of a UTF-8 sequence
+        let len = match byte {
+          0xc0..=0xdf => 2,
+          0xe0..=0xef => 3,
+          _ => 4,
+        };
+        let mut buf = vec![byte];
+        for _ in 1..len {
+          buf.push(self.read_byte()?);
+        }
+        match std::str::from_utf8(&buf)
+          .ok()
+          .and_then(|s| s.chars().next())
+        {
+          Some(c) => Key::Char(c),
+          None => Key::Unknown,
+        }
+      }
+    };
+    Ok(key)
+  }
+}
+
+impl Drop for Terminal {
+  fn drop(&mut self) {
+    let _ = self.tty.write_all(b"\x1b[?1049l\x1b[?25h");
+    unsafe {
+      libc::tcsetattr(
+        self.tty.as_raw_fd(),
+        libc::TCSAFLUSH,
+        &self.saved,
+      );
+    }
+  }
+}
+
+struct Picker<'a> {
+  entries: &'a [TrashEntry],
+  filter: String,
+  /// Indexes into `entries` that match the filter, best first
+  matches: Vec<usize>,
+  cursor: usize,
+  scroll: usize,
+  selected: HashSet<usize>,
+  sizes: HashMap<usize, u64>,
+  status: String,
+}
+
+/// Let the user pick trash entries by fuzzy filtering on name and
+/// original path. Returns what to do with the chosen entries, or
+/// `None` when cancelled.
+pub fn pick<'a>(
+  entries: &'a [TrashEntry],
+  filter: &str,
+) -> io::Result<Option<(Action, Vec<&'a TrashEntry>)>> {
+  let mut terminal = Terminal::open()?;
+  let mut picker = Picker {
+    entries,
+    filter: filter.to_string(),
+    matches: Vec::new(),
+    cursor: 0,
+    scroll: 0,
+    selected: HashSet::new(),
+    sizes: HashMap::new(),
+    status: String::new(),
+  };
+  picker.refilter();
+
+  loop {
+    picker.draw(&mut terminal)?;
+    let key = terminal.read_key()?;
+    picker.status.clear();
+    let page = terminal.size().1.saturating_sub(PREVIEW_ROWS + 1);
+    match key {
+      Key::Escape | Key::Ctrl(b'c') | Key::Ctrl(b'g') => {
+        return Ok(None)
+      }
+      Key::Up | Key::Ctrl(b'p') => picker.move_cursor(-1),
+      Key::Down | Key::Ctrl(b'n') => picker.move_cursor(1),
+      Key::PageUp => picker.move_cursor(-(page as isize)),
+      Key::PageDown => picker.move_cursor(page as isize),
+      Key::Tab => {
+        if let Some(&index) = picker.matches.get(picker.cursor) {
+          if !picker.selected.remove(&index) {
+            picker.selected.insert(index);
+          }
+          picker.move_cursor(1);
+        }
+      }
+      Key::Ctrl(b'a') => {
+        let all =
+          picker.matches.iter().all(|i| picker.selected.contains(i));
+        for &index in &picker.matches {
+          if all {
+            picker.selected.remove(&index);
+          } else {
+            picker.selected.insert(index);
+          }
+        }
+      }
+      Key::Backspace => {
+        picker.filter.pop();
+        picker.refilter();
+      }
+      Key::Ctrl(b'u') => {
+        picker.filter.clear();
+        picker.refilter();
+      }
+      Key::Char(c) if !c.is_control() => {
+        picker.filter.push(c);
+        picker.refilter();
+      }
+      Key::Enter => {
+        let chosen = picker.chosen();
+        if !chosen.is_empty() {
+          return Ok(Some((Action::Restore, chosen)));
+        }
+      }
+      Key::Ctrl(b'x') => {
+        let chosen = picker.chosen();
+        if chosen.is_empty() {
+          continue;
+        }
+        picker.status = format!(
+          "Permanently delete {} item{}? [y/N]",
+          chosen.len(),
+          if chosen.len() == 1 { "" } else { "s" }
+        );
+        picker.draw(&mut terminal)?;
+        picker.status.clear();
+        if matches!(terminal.read_key()?, Key::Char('y' | 'Y')) {
+          return Ok(Some((Action::Purge, chosen)));
+        }
+      }
+      _ => {}
+    }
+  }
+}
+
+/// Rows below the list: separator, three preview lines and help.
+const PREVIEW_ROWS: usize = 5;
+
+impl<'a> Picker<'a> {
+  fn refilter(&mut self) {
+    let pattern: Vec<char> =
+      self.filter.to_lowercase().chars().collect();
+    let mut scored: Vec<(usize, usize)> = self
+      .entries
+      .iter()
+      .enumerate()
+      .filter_map(|(i, entry)| {
+        let name = Path::new(&entry.name).to_string_lossy();
+        let original = entry.original_path.to_string_lossy();
+        // Name matches rank above matches on the original path only
+        let score = fuzzy_score(&pattern, &name).or_else(|| {
+          fuzzy_score(&pattern, &original).map(|s| s + 1000)
+        })?;
+        Some((score, i))
+      })
+      .collect();
+    // Stable, so equal scores keep the newest-first order
+    scored.sort_by_key(|&(score, _)| score);
+    self.matches = scored.into_iter().map(|(_, i)| i).collect();
+    self.cursor = 0;
+    self.scroll = 0;
+  }
+
+  fn move_cursor(&mut self, delta: isize) {
+    if self.matches.is_empty() {
+      return;
+    }
+    let last = self.matches.len() - 1;
+    self.cursor = self.cursor.saturating_add_signed(delta).min(last);
+  }
+
+  /// The selected entries, or the one under the cursor if none are.
+  fn chosen(&self) -> Vec<&'a TrashEntry> {
+    let entries = self.entries;
+    if self.selected.is_empty() {
+      return self
+        .matches
+        .get(self.cursor)
+        .map(|&i| &entries[i])
+        .into_iter()
+        .collect();
+    }
+    let mut indexes: Vec<usize> =
+      self.selected.iter().copied().collect();
+    indexes.sort();
+    indexes.into_iter().map(|i| &entries[i]).collect()
+  }
+
+  fn draw(&mut self, terminal: &mut Terminal) -> io::Result<()> {
+    let (width, height) = terminal.size();
+    let rows = height.saturating_sub(PREVIEW_ROWS + 1).max(1);
+    if self.cursor < self.scroll {
+      self.scroll = self.cursor;
+    } else if self.cursor >= self.scroll + rows {
+      self.scroll = self.cursor + 1 - rows;
+    }
+
+    let mut out = String::from("\x1b[?25l\x1b[H");
+    let count = format!(
+      "{}/{}{}",
+      self.matches.len(),
+      self.entries.len(),
+      match self.selected.len() {
+        0 => String::new(),
+        n => format!(" ({} selected)", n),
+      }
+    );
+    line(&mut out, &format!("> {}  {}", self.filter, count), width);
+
+    for row in 0..rows {
+      let Some(&index) = self.matches.get(self.scroll + row) else {
+        line(&mut out, "", width);
+        continue;
+      };
+      let entry = &self.entries[index];
+      let mark = if self.selected.contains(&index) {
+        "*"
+      } else {
+        " "
+      };
+      let text = format!(
+        "{} {}  \x1b[2m{}\x1b[22m",
+        mark,
+        printable(Path::new(&entry.name).display()),
+        printable(entry.original_path.display())
+      );
+      if self.scroll + row == self.cursor {
+        out.push_str("\x1b[7m");
+        line(&mut out, &text, width);
+        out.push_str("\x1b[27m");
+      } else {
+        line(&mut out, &text, width);
+      }
+    }
+
+    line(&mut out, &"─".repeat(width), width);
+    match self.matches.get(self.cursor) {
+      Some(&index) => {
+        let entry = &self.entries[index];
+        let size =
+          *self.sizes.entry(index).or_insert_with(|| entry.size());
+        line(
+          &mut out,
+          &format!(
+            "Original: {}",
+            printable(entry.original_path.display())
+          ),
+          width,
+        );
+        line(
+          &mut out,
+          &format!(
+            "Deleted:  {} ({})",
+            entry.formatted_date(DateFormat::Relative),
+            entry.formatted_date(DateFormat::Iso)
+          ),
+          width,
+        );
+        line(
+          &mut out,
+          &format!("Size:     {}", format_size(size)),
+          width,
+        );
+      }
+      None => {
+        for _ in 0..3 {
+          line(&mut out, "", width);
+        }
+      }
+    }
+    let help = if self.status.is_empty() {
+      "Enter restore  Ctrl-X purge  Tab select  Ctrl-A all  Esc quit"
+    } else {
+      &self.status
+    };
+    out.push_str("\x1b[2m");
+    out.push_str(&truncate(help, width));
+    out.push_str("\x1b[22m\x1b[K");
+
+    // Leave the cursor at the end of the filter
+    let column = 3 + self.filter.chars().count();
+    out.push_str(&format!("\x1b[1;{}H\x1b[?25h", column.min(width)));
+    terminal.tty.write_all(out.as_bytes())?;
+    terminal.tty.flush()
+  }
+}
+
+/// `text` with control characters shown as `?`, so a file name
+/// can't smuggle escape sequences to the terminal.
+fn printable(text: impl Display) -> String {
+  text
+    .to_string()
+    .chars()
+    .map(|c| if c.is_control() { '?' } else { c })
+    .collect()
+}
+
+/// Append `text` cut to `width` columns and clear the rest of the
+/// row. Escape sequences don't count towards the width.
+fn line(out: &mut String, text: &str, width: usize) {
+  out.push_str(&truncate(text, width));
+  out.push_str("\x1b[K\r\n");
+}
+
+fn truncate(text: &str, width: usize) -> String {
+  let mut out = String::new();
+  let mut columns = 0;
+  let mut chars = text.chars();
+  while let Some(c) = chars.next() {
+    if c == '\x1b' {
+      // Copy the whole CSI sequence
+      out.push(c);
+      for c in chars.by_ref() {
+        out.push(c);
+        if c.is_ascii_alphabetic() {
+          break;
+        }
+      }
+      continue;
+    }
+    if c.is_control() {
+      continue;
+    }
+    if columns == width {
+      break;
+    }
+    out.push(c);
+    columns += 1;
+  }
+  out
+}
+
+/// Match `pattern` as a case-insensitive subsequence of `text`.
+/// Lower scores are better: the number of characters skipped
+/// between the first and last matched character.
+fn fuzzy_score(pattern: &[char], text: &str) -> Option<usize> {
+  if pattern.is_empty() {
+    return Some(0);
+  }
+  let text: Vec<char> = text.to_lowercase().chars().collect();
+  let mut best = None;
+  // Try every start so the tightest match wins
+  for start in 0..text.len() {
+    if text[start] != pattern[0] {
+      continue;
+    }
+    let mut matched = 1;
+    let mut end = start;
+    for (i, &c) in text.iter().enumerate().skip(start + 1) {
+      if matched == pattern.len() {
+        break;
+      }
+      if c == pattern[matched] {
+        matched += 1;
+        end = i;
+      }
+    }
+    if matched == pattern.len() {
+      let gaps = end - start + 1 - pattern.len();
+      best = Some(best.map_or(gaps, |b: usize| b.min(gaps)));
+    }
+  }
+  best
+}
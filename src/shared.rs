@@ -1,23 +1,32 @@
+use crate::color;
+use crate::config::{
+  Config, DateFormat, ListFormat, ProtectAction, SortOrder,
+};
 use crate::fail;
 use crate::linux;
 use crate::macos;
+use crate::picker::{self, Action};
+use crate::{info, status, warn};
+use chrono::{Local, NaiveDateTime, TimeDelta, TimeZone};
 use optz::Optz;
 use std::env;
+use std::ffi::{OsStr, OsString};
 use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use std::process;
-use urlencoding::decode;
+use urlencoding::decode_binary;
 use xdg::BaseDirectories;
 
 #[derive(Debug, Clone)]
-struct TrashEntry {
-  name: String,
-  #[allow(dead_code)]
-  path: PathBuf,
-  #[allow(dead_code)]
-  info_path: PathBuf,
-  original_path: String,
-  deletion_date: String,
+pub struct TrashEntry {
+  pub name: OsString,
+  pub path: PathBuf,
+  pub info_path: PathBuf,
+  pub original_path: PathBuf,
+  pub deletion_date: String,
 }
 
 impl TrashEntry {
@@ -26,40 +35,36 @@ impl TrashEntry {
     info_path: &Path,
     home_trash: &Path,
   ) -> Option<Self> {
-    let info_name =
-      info_path.file_name()?.to_string_lossy().to_string();
-    if !info_name.ends_with(".trashinfo") {
-      return None;
-    }
-
-    let name = info_name.trim_end_matches(".trashinfo").to_string();
+    let info_name = info_path.file_name()?.as_bytes();
+    let name =
+      OsStr::from_bytes(info_name.strip_suffix(b".trashinfo")?)
+        .to_os_string();
     let files_path = files_dir.join(&name);
 
-    if !files_path.exists() {
+    if !path_exists(&files_path) {
       return None;
     }
 
     // Read the trashinfo file to get original path and deletion date
     let content = fs::read_to_string(info_path).ok()?;
-    let mut original_path = String::new();
+    let mut original_path = PathBuf::new();
     let mut deletion_date = String::new();
 
     for line in content.lines() {
-      if line.starts_with("Path=") {
-        let path_value = line.trim_start_matches("Path=").to_string();
-        // URL decode the path as per spec requirements
-        original_path = decode(&path_value)
-          .expect("Failed to decode path")
-          .to_string();
-      } else if line.starts_with("DeletionDate=") {
-        deletion_date =
-          line.trim_start_matches("DeletionDate=").to_string();
+      if let Some(path_value) = line.strip_prefix("Path=") {
+        // URL decode the path as per spec requirements. Decoding
+        // to raw bytes keeps non-UTF-8 names intact.
+        original_path = PathBuf::from(OsString::from_vec(
+          decode_binary(path_value.as_bytes()).into_owned(),
+        ));
+      } else if let Some(date) = line.strip_prefix("DeletionDate=") {
+        deletion_date = date.to_string();
       }
     }
 
     // Convert relative paths to absolute
-    let full_original_path = if original_path.starts_with('/') {
-      PathBuf::from(original_path)
+    let full_original_path = if original_path.is_absolute() {
+      original_path
     } else {
       // For relative paths:
       // - In home trash: relative to parent of trash directory
@@ -74,44 +79,691 @@ impl TrashEntry {
       name,
       path: files_path,
       info_path: info_path.to_path_buf(),
-      original_path: full_original_path.display().to_string(),
+      original_path: full_original_path,
       deletion_date,
     })
   }
+
+  /// The DeletionDate as local time, if it parses.
+  pub fn deleted_at(&self) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(
+      &self.deletion_date,
+      "%Y-%m-%dT%H:%M:%S",
+    )
+    .ok()
+  }
+
+  /// The deletion date in `format`, or as stored when it can't be
+  /// parsed.
+  pub fn formatted_date(&self, format: DateFormat) -> String {
+    let Some(date) = self
+      .deleted_at()
+      .and_then(|date| Local.from_local_datetime(&date).earliest())
+    else {
+      return self.deletion_date.clone();
+    };
+    match format {
+      DateFormat::Relative => relative_date(Local::now() - date),
+      DateFormat::Iso => date.to_rfc3339(),
+      DateFormat::Local => date.format("%c").to_string(),
+      DateFormat::Unix => date.timestamp().to_string(),
+    }
+  }
+
+  /// Size of the trashed file, or everything under it for a
+  /// directory. Symlinks count as themselves.
+  pub fn size(&self) -> u64 {
+    match fs::symlink_metadata(&self.path) {
+      Ok(m) if m.is_dir() => {
+        linux::calculate_directory_size(&self.path).unwrap_or(0)
+      }
+      Ok(m) => m.len(),
+      Err(_) => 0,
+    }
+  }
+
+  /// Permanently delete the entry. The payload goes first so a
+  /// failure never leaves it without its trashinfo.
+  pub fn remove(&self) -> io::Result<()> {
+    if fs::symlink_metadata(&self.path)?.is_dir() {
+      fs::remove_dir_all(&self.path)?;
+    } else {
+      fs::remove_file(&self.path)?;
+    }
+    fs::remove_file(&self.info_path)
+  }
 }
 
-pub fn empty(_optz: &Optz, verbose: bool) {
+pub fn empty(_optz: &Optz, config: &Config, verbose: bool) {
+  if let Some(days) = config.empty.retention_days {
+    return purge_older_than(days, config, verbose);
+  }
   match env::consts::OS {
-    "macos" => macos::empty_trash(verbose),
-    "linux" => linux::empty_trash(verbose),
+    "macos" => macos::empty_trash(),
+    "linux" => linux::empty_trash(config, verbose),
     _ => fail!("can: OS not supported"),
   }
 }
 
-pub fn list(_optz: &Optz, verbose: bool) {
-  let mut entries = get_all_trash_entries();
+/// Purge only the entries deleted more than `days` days ago.
+fn purge_older_than(days: u64, config: &Config, verbose: bool) {
+  if env::consts::OS != "linux" {
+    fail!("can: --older-than is not supported on this OS");
+  }
+  let cutoff = Local::now().naive_local()
+    - chrono::Duration::days(days.min(i64::MAX as u64) as i64);
+  let mut purged = 0;
+  let mut had_errors = false;
+
+  for entry in get_all_trash_entries(config, verbose) {
+    // Entries without a readable date are kept, never guessed at
+    if entry.deleted_at().is_none_or(|date| date >= cutoff) {
+      continue;
+    }
+    match entry.remove() {
+      Ok(()) => {
+        purged += 1;
+        info!("Purged {}", entry.path.display());
+      }
+      Err(e) => {
+        warn!(
+          "Warning: Failed to remove {}: {}",
+          entry.path.display(),
+          e
+        );
+        had_errors = true;
+      }
+    }
+  }
+
+  if had_errors {
+    warn!("Warning: Some items could not be removed from trash");
+  }
+  if !had_errors {
+    info!(
+      "Purged {} item{} older than {} day{}",
+      purged,
+      if purged == 1 { "" } else { "s" },
+      days,
+      if days == 1 { "" } else { "s" }
+    );
+  }
+}
+
+/// Describe how long ago `age` was, e.g. `3 hours ago`.
+fn relative_date(age: TimeDelta) -> String {
+  let seconds = age.num_seconds();
+  let (count, unit) = match seconds.abs() {
+    s if s < 60 => return "just now".to_string(),
+    s if s < 3600 => (s / 60, "minute"),
+    s if s < 86400 => (s / 3600, "hour"),
+    s if s < 7 * 86400 => (s / 86400, "day"),
+    s if s < 30 * 86400 => (s / (7 * 86400), "week"),
+    s if s < 365 * 86400 => (s / (30 * 86400), "month"),
+    s => (s / (365 * 86400), "year"),
+  };
+  let unit = format!(
+    "{} {}{}",
+    count,
+    unit,
+    if count == 1 { "" } else { "s" }
+  );
+  if seconds < 0 {
+    format!("in {}", unit)
+  } else {
+    format!("{} ago", unit)
+  }
+}
+
+pub fn list(_optz: &Optz, config: &Config, verbose: bool) {
+  let mut entries = get_all_trash_entries(config, verbose);
 
-  // Sort entries by name for consistent output
+  // Sort by name first so ties in the other orders are stable
   entries.sort_by(|a, b| a.name.cmp(&b.name));
+  match config.list.sort {
+    SortOrder::Name => {}
+    SortOrder::Date => {
+      entries.sort_by_key(|entry| entry.deleted_at())
+    }
+    SortOrder::Original => {
+      entries.sort_by(|a, b| a.original_path.cmp(&b.original_path))
+    }
+  }
 
   if entries.is_empty() {
-    println!("Trash is empty");
+    status!("Trash is empty");
     return;
   }
 
   for entry in entries {
-    if verbose {
+    if config.list.format == ListFormat::Long {
       println!(
         "{} (deleted: {}, original: {})",
-        entry.name, entry.deletion_date, entry.original_path
+        color::name(&entry.path, &entry.name),
+        entry.formatted_date(config.list.date),
+        color::dim(entry.original_path.display())
       );
     } else {
-      println!("{}", entry.name);
+      println!("{}", color::name(&entry.path, &entry.name));
+    }
+  }
+}
+
+/// Move the named trash entries back to their original locations.
+/// Every name is looked up before anything is moved.
+pub fn restore(
+  names: &[OsString],
+  config: &Config,
+  verbose: bool,
+  newest: bool,
+) {
+  let entries = get_all_trash_entries(config, verbose);
+  let selected = select_entries(&entries, names, newest);
+  restore_entries(&selected);
+}
+
+/// Pick entries to restore or purge in a full screen picker.
+/// `filter` is the initial search text.
+pub fn restore_interactive(
+  filter: &str,
+  config: &Config,
+  verbose: bool,
+) {
+  let mut entries = get_all_trash_entries(config, verbose);
+  if entries.is_empty() {
+    status!("Trash is empty");
+    return;
+  }
+  // Newest first, which is what's usually being looked for
+  entries.sort_by(|a, b| b.deletion_date.cmp(&a.deletion_date));
+
+  match picker::pick(&entries, filter) {
+    Ok(Some((Action::Restore, chosen))) => restore_entries(&chosen),
+    Ok(Some((Action::Purge, chosen))) => purge_entries(&chosen),
+    Ok(None) => {}
+    Err(e) => fail!("can: Can't use the terminal: {}", e),
+  }
+}
+
+fn restore_entries(entries: &[&TrashEntry]) {
+  for entry in entries {
+    if let Err(e) = linux::restore_entry(entry) {
+      fail!(
+        "can: {}: Failed to restore to {}: {}",
+        Path::new(&entry.name).display(),
+        entry.original_path.display(),
+        e
+      );
+    }
+    info!("Restored {}", entry.original_path.display());
+  }
+}
+
+/// Permanently delete the named trash entries.
+pub fn purge(
+  names: &[OsString],
+  config: &Config,
+  verbose: bool,
+  newest: bool,
+) {
+  let entries = get_all_trash_entries(config, verbose);
+  let selected = select_entries(&entries, names, newest);
+  purge_entries(&selected);
+}
+
+fn purge_entries(entries: &[&TrashEntry]) {
+  for entry in entries {
+    if let Err(e) = entry.remove() {
+      fail!("can: {}: Failed to purge: {}", entry.path.display(), e);
+    }
+    info!("Purged {}", Path::new(&entry.name).display());
+  }
+}
+
+/// Default for `can cat --max-size`.
+pub const CAT_MAX_SIZE: u64 = 1 << 20;
+
+/// Print the contents of the named trashed files. Binary files are
+/// refused when writing to a terminal, and output stops after
+/// `max_size` bytes.
+pub fn cat(
+  names: &[OsString],
+  config: &Config,
+  verbose: bool,
+  binary: bool,
+  max_size: Option<u64>,
+) {
+  let entries = get_all_trash_entries(config, verbose);
+  let selected = select_entries(&entries, names, false);
+  let to_terminal = unsafe { libc::isatty(libc::STDOUT_FILENO) } != 0;
+  let limit = max_size.unwrap_or(u64::MAX);
+
+  for entry in selected {
+    let name = Path::new(&entry.name).display();
+    let metadata = match fs::symlink_metadata(&entry.path) {
+      Ok(metadata) => metadata,
+      Err(e) => fail!("can: {}: {}", name, e),
+    };
+    if metadata.is_dir() {
+      fail!("can: {}: Is a directory (see `can tree`)", name);
+    }
+    if metadata.file_type().is_symlink() {
+      let target = fs::read_link(&entry.path).unwrap_or_default();
+      fail!("can: {}: Is a symlink to {}", name, target.display());
+    }
+    if !metadata.is_file() {
+      fail!("can: {}: Not a regular file", name);
+    }
+
+    let res = fs::File::open(&entry.path).and_then(|file| {
+      let mut file = file.take(limit);
+      // Judge by the first block, like grep and git do
+      let mut head = Vec::new();
+      (&mut file).take(8192).read_to_end(&mut head)?;
+      if to_terminal && !binary && looks_binary(&head) {
+        fail!(
+          "can: {}: Binary file (use --binary to print it anyway)",
+          name
+        );
+      }
+      let mut stdout = io::stdout().lock();
+      stdout.write_all(&head)?;
+      io::copy(&mut file, &mut stdout)?;
+      stdout.flush()
+    });
+    match res {
+      Ok(()) => {}
+      Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return,
+      Err(e) => fail!("can: {}: {}", name, e),
+    }
+
+    if metadata.len() > limit {
+      status!(
+        "can: {}: Stopped after {} of {} (use --max-size none to print all of it)",
+        name,
+        format_size(limit),
+        format_size(metadata.len())
+      );
+    }
+  }
+}
+
+/// Whether `head` looks like the start of a binary file: it has a
+/// NUL byte or isn't valid UTF-8.
+fn looks_binary(head: &[u8]) -> bool {
+  // A multi-byte character cut off at the end is still text
+  head.contains(&0)
+    || matches!(std::str::from_utf8(head), Err(e) if e.error_len().is_some())
+}
+
+/// List the items trashed from `arg`, newest first and numbered as
+/// `arg@N` picks them. With `diff`, each is compared with what is at
+/// that path now. `arg@N` limits this to one version.
+pub fn versions(
+  arg: &OsStr,
+  config: &Config,
+  verbose: bool,
+  diff: bool,
+) {
+  let entries = get_all_trash_entries(config, verbose);
+  let mut versions = trashed_from(&entries, Path::new(arg));
+  let mut base = arg;
+  let mut only = None;
+  if versions.is_empty() {
+    if let Some((path, n)) = split_index(arg) {
+      versions = trashed_from(&entries, Path::new(path));
+      base = path;
+      only = Some(n);
+    }
+  }
+  if versions.is_empty() {
+    fail!("can: {}: No such item in trash", Path::new(arg).display());
+  }
+  if let Some(n) = only.filter(|&n| n == 0 || n > versions.len()) {
+    fail!(
+      "can: {}: No match number {}, there are {}",
+      Path::new(arg).display(),
+      n,
+      versions.len()
+    );
+  }
+
+  let dates: Vec<String> = versions
+    .iter()
+    .map(|entry| entry.formatted_date(config.list.date))
+    .collect();
+  let width =
+    dates.iter().map(|d| d.chars().count()).max().unwrap_or(0);
+  for (i, entry) in versions.iter().enumerate() {
+    if only.is_some_and(|n| n != i + 1) {
+      continue;
+    }
+    println!(
+      "{}  {:<width$}  {:>9}  {}  {}",
+      i + 1,
+      dates[i],
+      format_size(entry.size()),
+      color::name(&entry.path, &entry.name),
+      color::dim(entry.original_path.display())
+    );
+    if diff {
+      diff_with_current(
+        entry,
+        &format!("{}@{}", Path::new(base).display(), i + 1),
+      );
+    }
+  }
+}
+
+/// Show how the trashed `entry` differs from what is at its original
+/// path now, using diff(1).
+fn diff_with_current(entry: &TrashEntry, label: &str) {
+  let current = &entry.original_path;
+  let Ok(metadata) = fs::symlink_metadata(current) else {
+    status!("can: {} does not exist now", current.display());
+    return;
+  };
+  let mut command = process::Command::new("diff");
+  if metadata.is_dir() {
+    command.arg("-ru");
+  } else {
+    command
+      .arg("-u")
+      .arg("-L")
+      .arg(current)
+      .arg("-L")
+      .arg(label);
+  }
+  let _ = io::stdout().flush();
+  // diff exits with 1 when the files differ, and 2 on trouble
+  match command.arg("--").arg(current).arg(&entry.path).status() {
+    Ok(status) if status.code() == Some(2) => {
+      fail!("can: diff failed for {}", label)
     }
+    Ok(_) => {}
+    Err(e) => fail!("can: Can't run diff: {}", e),
   }
 }
 
-pub fn get_all_trash_paths() -> Vec<PathBuf> {
+/// Show the files inside the named trash entries as a tree.
+pub fn tree(names: &[OsString], config: &Config, verbose: bool) {
+  let entries = get_all_trash_entries(config, verbose);
+  let selected = select_entries(&entries, names, false);
+
+  let mut stdout = io::stdout().lock();
+  let res = selected.iter().try_for_each(|entry| {
+    writeln!(stdout, "{}", tree_label(&entry.path, &entry.name))?;
+    if !fs::symlink_metadata(&entry.path).is_ok_and(|m| m.is_dir()) {
+      return Ok(());
+    }
+    let mut counts = (0, 0);
+    print_tree(&mut stdout, &entry.path, "", &mut counts)?;
+    writeln!(
+      stdout,
+      "\n{} director{}, {} file{}",
+      counts.0,
+      if counts.0 == 1 { "y" } else { "ies" },
+      counts.1,
+      if counts.1 == 1 { "" } else { "s" }
+    )
+  });
+  match res.and_then(|_| stdout.flush()) {
+    Ok(()) => {}
+    Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+    Err(e) => fail!("can: {}", e),
+  }
+}
+
+/// Print the contents of `dir` below `prefix`, counting
+/// directories and other files in `counts`.
+fn print_tree(
+  out: &mut impl Write,
+  dir: &Path,
+  prefix: &str,
+  counts: &mut (usize, usize),
+) -> io::Result<()> {
+  let mut children: Vec<PathBuf> = match fs::read_dir(dir) {
+    Ok(entries) => entries.flatten().map(|e| e.path()).collect(),
+    Err(e) => {
+      warn!("Warning: Failed to read {}: {}", dir.display(), e);
+      return Ok(());
+    }
+  };
+  children.sort();
+
+  for (i, child) in children.iter().enumerate() {
+    let last = i + 1 == children.len();
+    let name = child.file_name().unwrap_or_default();
+    writeln!(
+      out,
+      "{}{}{}",
+      prefix,
+      if last { "└── " } else { "├── " },
+      tree_label(child, name)
+    )?;
+    if fs::symlink_metadata(child).is_ok_and(|m| m.is_dir()) {
+      counts.0 += 1;
+      let prefix =
+        format!("{}{}", prefix, if last { "    " } else { "│   " });
+      print_tree(out, child, &prefix, counts)?;
+    } else {
+      counts.1 += 1;
+    }
+  }
+  Ok(())
+}
+
+/// `name/` for directories and `name -> target` for symlinks.
+fn tree_label(path: &Path, name: &OsStr) -> String {
+  let name = color::name(path, name);
+  match fs::symlink_metadata(path) {
+    Ok(m) if m.is_dir() => format!("{}/", name),
+    Ok(m) if m.file_type().is_symlink() => {
+      let target = fs::read_link(path).unwrap_or_default();
+      format!("{} -> {}", name, target.display())
+    }
+    _ => name.to_string(),
+  }
+}
+
+/// Format a byte count for people, e.g. `1.5 MiB`.
+pub fn format_size(bytes: u64) -> String {
+  const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+  if bytes < 1024 {
+    return format!("{} B", bytes);
+  }
+  let mut size = bytes as f64 / 1024.0;
+  let mut unit = 0;
+  while size >= 1024.0 && unit < UNITS.len() - 1 {
+    size /= 1024.0;
+    unit += 1;
+  }
+  format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Print the names of trashed items starting with `prefix`, one
+/// per line, for shell completion.
+pub fn complete_entries(prefix: &OsStr, config: &Config) {
+  let mut names: Vec<OsString> = get_all_trash_entries(config, false)
+    .into_iter()
+    .map(|entry| entry.name)
+    .filter(|name| name.as_bytes().starts_with(prefix.as_bytes()))
+    .collect();
+  names.sort();
+  names.dedup();
+
+  let mut stdout = io::stdout().lock();
+  for name in names {
+    let _ = stdout
+      .write_all(name.as_bytes())
+      .and_then(|_| stdout.write_all(b"\n"));
+  }
+}
+
+/// Look up the entries `names` refer to, in order and without
+/// repeats. See `find_entry`.
+fn select_entries<'a>(
+  entries: &'a [TrashEntry],
+  names: &[OsString],
+  newest: bool,
+) -> Vec<&'a TrashEntry> {
+  let mut selected: Vec<&TrashEntry> = Vec::new();
+  for name in names {
+    let entry = find_entry(entries, name, newest);
+    if !selected.iter().any(|e| e.info_path == entry.info_path) {
+      selected.push(entry);
+    }
+  }
+  selected
+}
+
+/// Find the entry `arg` refers to: the path it was trashed from or
+/// its name in the trash. Several matches are numbered versions of
+/// the path first, as `versions` numbers them, then other entries
+/// of that name. `@N` picks the Nth, `newest` the first, and
+/// otherwise the list is offered to choose from.
+fn find_entry<'a>(
+  entries: &'a [TrashEntry],
+  arg: &OsStr,
+  newest: bool,
+) -> &'a TrashEntry {
+  let mut index = None;
+  let mut candidates = matching_entries(entries, arg);
+  if candidates.is_empty() {
+    if let Some((base, n)) = split_index(arg) {
+      candidates = matching_entries(entries, base);
+      index = Some(n);
+    }
+  }
+
+  let arg = Path::new(arg).display();
+  match (candidates.as_slice(), index) {
+    ([], _) => fail!("can: {}: No such item in trash", arg),
+    (_, Some(n)) => match candidates.get(n.wrapping_sub(1)) {
+      Some(entry) => entry,
+      None => fail!(
+        "can: {}: No match number {}, there are {}",
+        arg,
+        n,
+        candidates.len()
+      ),
+    },
+    ([entry], None) => entry,
+    ([entry, ..], None) if newest => entry,
+    (_, None) => {
+      let list: Vec<String> = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, e)| {
+          format!(
+            "  {}) {}  deleted {}  from {}",
+            i + 1,
+            Path::new(&e.name).display(),
+            e.formatted_date(DateFormat::Relative),
+            e.original_path.display()
+          )
+        })
+        .collect();
+      if unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
+        fail!(
+          "can: {}: Matches several items:\n{}\nPick one with `{}@N` or use --newest",
+          arg,
+          list.join("\n"),
+          arg
+        );
+      }
+      eprintln!("can: {}: Matches several items:", arg);
+      eprintln!("{}", list.join("\n"));
+      eprint!("Which one? [1-{}] ", candidates.len());
+      let _ = io::stderr().flush();
+      let mut answer = String::new();
+      let _ = io::stdin().read_line(&mut answer);
+      match answer.trim().parse::<usize>() {
+        Ok(n) if (1..=candidates.len()).contains(&n) => {
+          candidates[n - 1]
+        }
+        _ => process::exit(1),
+      }
+    }
+  }
+}
+
+/// Entries trashed from the path `arg`, followed by the other
+/// entries named `arg` in the trash, each newest first. The two
+/// lookups are kept apart so neither hides the other's matches.
+fn matching_entries<'a>(
+  entries: &'a [TrashEntry],
+  arg: &OsStr,
+) -> Vec<&'a TrashEntry> {
+  let mut matches = trashed_from(entries, Path::new(arg));
+  let named =
+    newest_first(entries.iter().filter(|e| e.name == arg).collect());
+  for entry in named {
+    if !matches.iter().any(|m| m.info_path == entry.info_path) {
+      matches.push(entry);
+    }
+  }
+  matches
+}
+
+/// Entries trashed from `path`, newest first.
+fn trashed_from<'a>(
+  entries: &'a [TrashEntry],
+  path: &Path,
+) -> Vec<&'a TrashEntry> {
+  let path = original_path_of(path);
+  newest_first(
+    entries.iter().filter(|e| e.original_path == path).collect(),
+  )
+}
+
+fn newest_first(mut matches: Vec<&TrashEntry>) -> Vec<&TrashEntry> {
+  // DeletionDate only has whole seconds, so the trashinfo's mtime
+  // breaks ties
+  let info_mtime = |e: &TrashEntry| {
+    fs::metadata(&e.info_path).and_then(|m| m.modified()).ok()
+  };
+  matches.sort_by(|a, b| {
+    b.deletion_date
+      .cmp(&a.deletion_date)
+      .then_with(|| info_mtime(b).cmp(&info_mtime(a)))
+  });
+  matches
+}
+
+/// Split `name@N` into `name` and N.
+fn split_index(arg: &OsStr) -> Option<(&OsStr, usize)> {
+  let bytes = arg.as_bytes();
+  let at = bytes.iter().rposition(|&b| b == b'@')?;
+  let n = std::str::from_utf8(&bytes[at + 1..]).ok()?.parse().ok()?;
+  Some((OsStr::from_bytes(&bytes[..at]), n))
+}
+
+/// The absolute path `path` would have been trashed from. Its
+/// parent may no longer exist, in which case `.` and `..` are
+/// resolved without looking at the filesystem.
+fn original_path_of(path: &Path) -> PathBuf {
+  if let Ok(path) = absolute_path(path) {
+    return path;
+  }
+  let path = env::current_dir().unwrap_or_default().join(path);
+  let mut normalized = PathBuf::new();
+  for component in path.components() {
+    match component {
+      std::path::Component::CurDir => {}
+      std::path::Component::ParentDir => {
+        normalized.pop();
+      }
+      c => normalized.push(c),
+    }
+  }
+  normalized
+}
+
+pub fn get_all_trash_paths(
+  config: &Config,
+  verbose: bool,
+) -> Vec<PathBuf> {
   let mut trash_paths = Vec::new();
 
   // Always include home trash
@@ -120,40 +772,51 @@ pub fn get_all_trash_paths() -> Vec<PathBuf> {
 
   if env::consts::OS == "linux" {
     // Add per-device trash directories
-    trash_paths.extend(linux::get_topdir_trash_paths());
+    trash_paths.extend(linux::get_topdir_trash_paths(
+      &config.filesystems,
+      verbose,
+    ));
   }
 
   trash_paths
 }
 
-fn get_all_trash_entries() -> Vec<TrashEntry> {
-  let mut entries = Vec::new();
-  let trash_paths = get_all_trash_paths();
-
-  for trash_path in trash_paths {
-    let files_dir = trash_path.join("files");
-    let info_dir = trash_path.join("info");
+fn get_all_trash_entries(
+  config: &Config,
+  verbose: bool,
+) -> Vec<TrashEntry> {
+  let mut trash_paths = get_all_trash_paths(config, verbose);
+  // The home trash may also be listed as a per-device trash
+  trash_paths.sort();
+  trash_paths.dedup();
+  trash_paths
+    .iter()
+    .flat_map(|t| get_trash_entries(t))
+    .collect()
+}
 
-    if !files_dir.exists() || !info_dir.exists() {
-      continue;
-    }
+/// All valid entries of the single trash directory at `trash_path`.
+pub fn get_trash_entries(trash_path: &Path) -> Vec<TrashEntry> {
+  let files_dir = trash_path.join("files");
+  let info_dir = trash_path.join("info");
 
-    if let Ok(info_entries) = fs::read_dir(&info_dir) {
-      for info_entry in info_entries {
-        if let Ok(info_entry) = info_entry {
-          if let Some(entry) = TrashEntry::from_paths(
-            &files_dir,
-            &info_entry.path(),
-            &trash_path,
-          ) {
-            entries.push(entry);
-          }
-        }
-      }
-    }
+  if !path_exists(&files_dir) {
+    return Vec::new();
   }
 
-  entries
+  let Ok(info_entries) = fs::read_dir(&info_dir) else {
+    return Vec::new();
+  };
+  info_entries
+    .flatten()
+    .filter_map(|info_entry| {
+      TrashEntry::from_paths(
+        &files_dir,
+        &info_entry.path(),
+        trash_path,
+      )
+    })
+    .collect()
 }
 
 pub fn get_home_trash_path() -> PathBuf {
@@ -169,35 +832,103 @@ pub fn get_home_trash_path() -> PathBuf {
       let home = xdg
         .get_data_home()
         .unwrap_or_else(|| fail!("can: Can't find HOME directory"));
-      PathBuf::from(home).join("Trash")
+      home.join("Trash")
     }
     _ => fail!("can: OS not supported"),
   }
 }
 
-pub fn move_files_to_trash(optz: &Optz, verbose: bool) {
-  let mut to_delete: Vec<String> = Vec::new();
+pub fn move_files_to_trash(
+  files: &[OsString],
+  config: &Config,
+  verbose: bool,
+  force_dangerous: bool,
+) {
+  let mut to_delete: Vec<PathBuf> = Vec::new();
+  // Labels for verbose output, colored while the files are still
+  // in place
+  let mut trashed: Vec<String> = Vec::new();
+  // Canonical like the paths they're compared with, so a symlinked
+  // data directory can't hide a trash
+  let trash_paths: Vec<PathBuf> =
+    get_all_trash_paths(config, verbose)
+      .into_iter()
+      .map(|t| fs::canonicalize(&t).unwrap_or(t))
+      .collect();
 
   // Validate all paths before processing
-  for arg in &optz.rest {
-    let path = Path::new(&arg);
+  for arg in files {
+    let path = Path::new(arg);
+
+    if !path_exists(path) {
+      fail!("can: {}: No such file or directory", path.display());
+    }
+
+    // Resolve relative components without following the entry
+    // itself, so a symlink is trashed rather than its target
+    let abs_path = match absolute_path(path) {
+      Ok(abs_path) => abs_path,
+      Err(e) => {
+        fail!("can: {}: Canonicalize failed: {}", path.display(), e)
+      }
+    };
 
-    if !path.exists() {
-      fail!("can: {}: No such file or directory", arg);
+    if !force_dangerous {
+      if let Some(reason) = dangerous_target(&abs_path, &trash_paths)
+      {
+        fail!(
+          "can: {}: Refusing to trash {}",
+          path.display(),
+          reason
+        );
+      }
     }
 
-    // Canonicalize to resolve symlinks and relative components
-    match fs::canonicalize(path) {
-      Ok(abs_path) => to_delete.push(abs_path.display().to_string()),
-      Err(e) => fail!("can: {}: Canonicalize failed: {}", arg, e),
+    if let Some((protected, pattern)) =
+      config.protect.matching(&abs_path)
+    {
+      let what = if protected == abs_path {
+        "a protected path".to_string()
+      } else {
+        format!("protected {}", protected.display())
+      };
+      match config.protect.action {
+        ProtectAction::Refuse => fail!(
+          "can: {}: Refusing to trash {} (matches `{}`)",
+          path.display(),
+          what,
+          pattern
+        ),
+        ProtectAction::Confirm => {
+          let question = format!(
+            "can: {} would trash {} (matches `{}`). Trash it anyway?",
+            path.display(),
+            what,
+            pattern
+          );
+          if !confirm(&question) {
+            continue;
+          }
+        }
+      }
+    }
+
+    to_delete.push(abs_path);
+    trashed.push(color::name(path, arg));
+  }
+
+  if let Some(threshold) = config.put.confirm_threshold {
+    if threshold > 0 && to_delete.len() > threshold {
+      let question = format!("can: Trash {} items?", to_delete.len());
+      if !confirm(&question) {
+        process::exit(1);
+      }
     }
   }
 
   // Early exit if no valid files to delete
   if to_delete.is_empty() {
-    if verbose {
-      println!("No valid files to delete");
-    }
+    info!("No valid files to delete");
     process::exit(0);
   }
 
@@ -206,16 +937,107 @@ pub fn move_files_to_trash(optz: &Optz, verbose: bool) {
       macos::move_file_to_trash(&to_delete);
     }
     "linux" => {
-      linux::move_file_to_trash(&to_delete);
+      linux::move_file_to_trash(&to_delete, &config.put, verbose);
     }
     _ => fail!("can: OS not supported"),
   }
 
   if verbose {
-    for arg in &optz.rest {
-      println!("{}", arg);
+    for label in trashed {
+      println!("{}", label);
     }
   }
 
   process::exit(0);
 }
+
+/// Ask a yes/no question on the terminal. Without a terminal to ask
+/// on, the answer is no.
+fn confirm(question: &str) -> bool {
+  if unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
+    eprintln!("{} [y/N] n (stdin is not a terminal)", question);
+    return false;
+  }
+  eprint!("{} [y/N] ", question);
+  let _ = io::stderr().flush();
+  let mut answer = String::new();
+  if io::stdin().read_line(&mut answer).is_err() {
+    return false;
+  }
+  matches!(answer.trim(), "y" | "Y" | "yes" | "Yes" | "YES")
+}
+
+/// Explain why trashing `path` would be a mistake, if it would be.
+/// Trash directories can't hold themselves, and the root, mount
+/// points and the home directory are almost always typos.
+fn dangerous_target(
+  path: &Path,
+  trash_paths: &[PathBuf],
+) -> Option<String> {
+  let in_trash = trash_paths.iter().any(|t| path.starts_with(t))
+    || path.ancestors().any(|p| {
+      p.file_name().is_some_and(|name| {
+        let name = name.to_string_lossy();
+        name == ".Trash" || name.starts_with(".Trash-")
+      })
+    });
+  if in_trash {
+    return Some(
+      "a trash directory or its contents (use `can purge` or `can empty` to delete trashed files permanently, or --force-dangerous to override)"
+        .to_string(),
+    );
+  }
+
+  let home =
+    env::var_os("HOME").and_then(|home| fs::canonicalize(home).ok());
+
+  let reason = if path.parent().is_none() {
+    "the filesystem root"
+  } else if home.as_deref() == Some(path) {
+    "the home directory"
+  } else if home.is_some_and(|home| home.starts_with(path)) {
+    "a directory containing the home directory"
+  } else if is_mount_point(path) {
+    "a mount point"
+  } else {
+    return None;
+  };
+  Some(format!("{} (use --force-dangerous to override)", reason))
+}
+
+fn is_mount_point(path: &Path) -> bool {
+  let Some(parent) = path.parent() else {
+    return true;
+  };
+  // A symlink is never a mount point, whatever it points to
+  match (fs::symlink_metadata(path), fs::metadata(parent)) {
+    (Ok(m), _) if m.file_type().is_symlink() => return false,
+    (Ok(m), Ok(p)) if m.dev() != p.dev() => return true,
+    _ => {}
+  }
+  // Bind mounts of the same filesystem share a device id
+  env::consts::OS == "linux"
+    && linux::get_mount_points().iter().any(|m| m == path)
+}
+
+/// Check whether anything exists at `path` without following
+/// symlinks, so dangling symlinks still count as present.
+pub fn path_exists(path: &Path) -> bool {
+  fs::symlink_metadata(path).is_ok()
+}
+
+/// Make `path` absolute by canonicalizing only its parent directory.
+/// The final component is left as-is so symlinks aren't followed.
+fn absolute_path(path: &Path) -> io::Result<PathBuf> {
+  match path.file_name() {
+    Some(name) => {
+      let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+      };
+      Ok(fs::canonicalize(parent)?.join(name))
+    }
+    // ".", ".." and "/" have no file name to preserve
+    None => fs::canonicalize(path),
+  }
+}